@@ -1,70 +1,321 @@
 use crate::blocks::block_kind;
-use crate::ga::{GenoSeq, GenomeKind};
+use crate::ga::{GenoSeq, GenomeKind, GENOME_LEN};
 use crate::game::*;
+use crate::input::{Action, Controller, Handling};
+use crate::movegen::{cells, placements, placements_from, Placement};
+use std::collections::VecDeque;
 
-pub fn eval(game: &Game, weight: &GenoSeq) -> Game {
-    // elite block (Game, score)
-    let mut elite = (game.clone(), 0f64);
+// hand-tuned weights of the bot
+pub const WEIGHTS: GenoSeq = [100, 1, 10, 100, 5, 20, 5, 20, 5, 5];
 
-    // enable disable hold
-    for do_hold in [true, false] {
-        let mut game = game.clone();
-        if do_hold {
-            hold(&mut game);
-        }
+// milliseconds between the inputs of the auto mode
+pub const INPUT_DELAY_MSEC: u64 = 30;
 
-        // all rotate
-        for rotate_count in 0..=3 {
-            let mut game = game.clone();
-            for _ in 0..=rotate_count {
-                // rotate process
-                rotate_right(&mut game);
+// placement chosen by the bot
+#[derive(Clone)]
+pub struct Choice {
+    // hold before the placement
+    pub hold: bool,
+    pub placement: Placement,
+}
+
+impl Choice {
+    // keys to press in order
+    pub fn inputs(&self) -> Vec<Action> {
+        let hold = self.hold.then_some(Action::Hold);
+        hold.into_iter()
+            .chain(self.placement.path.iter().copied())
+            .collect()
+    }
+}
+
+// move the block to the choice at once, the block is fixed by landing
+pub fn place(game: &mut Game, choice: &Choice) {
+    if choice.hold {
+        hold(game);
+    }
+    game.piece = choice.placement.piece;
+}
+
+// how far the bot looks ahead
+#[derive(Clone, Copy)]
+pub struct Lookahead {
+    // number of blocks to place, from the block in play to the next blocks
+    pub depth: usize,
+    // number of placements kept at each depth
+    pub width: usize,
+}
+
+impl Lookahead {
+    // the best placement of the block in play only
+    pub const GREEDY: Lookahead = Lookahead { depth: 1, width: 1 };
+}
+
+// default lookahead of the bots
+pub const LOOKAHEAD: Lookahead = Lookahead { depth: 2, width: 8 };
+
+pub fn eval(game: &Game, weight: &GenoSeq) -> Choice {
+    search(game, weight, Lookahead::GREEDY)
+}
+
+// a game in the beam search
+struct Node {
+    game: Game,
+    // the first choice to get there
+    choice: Option<Choice>,
+    // sum of the scores of the placements
+    score: f64,
+    // number of the next blocks brought into play
+    seen: usize,
+}
+
+// beam search over the block in play, the hold and the next blocks in sight
+pub fn search(game: &Game, weight: &GenoSeq, lookahead: Lookahead) -> Choice {
+    // drop the block in play if it can not be placed anywhere
+    let placement = Placement {
+        piece: game.piece,
+        path: vec![Action::HardDrop],
+    };
+    let mut elite = Choice {
+        hold: false,
+        placement,
+    };
+
+    let mut beam = vec![Node {
+        game: game.clone(),
+        choice: None,
+        score: 0.0,
+        seen: 0,
+    }];
+    for depth in 0..lookahead.depth {
+        let last = depth + 1 == lookahead.depth;
+        let mut nodes = vec![];
+        for node in &beam {
+            // enable disable hold
+            for do_hold in [true, false] {
+                let mut game = node.game.clone();
+                let mut seen = node.seen;
+                if do_hold {
+                    // the hold is used once for each block
+                    if game.holded {
+                        continue;
+                    }
+                    if game.hold.is_none() {
+                        seen += 1;
+                    }
+                    hold(&mut game);
+                }
+                // the blocks not shown yet are not used
+                if seen > NEXT_LENGTH {
+                    continue;
+                }
+
+                // all reachable placements
+                for placement in reachable(&game) {
+                    let mut game = game.clone();
+                    game.piece = placement.piece;
+                    fix_block(&mut game);
+                    let score = node.score + evaluate(&game, weight);
+                    if !last && landing(&mut game).is_err() {
+                        continue;
+                    }
+                    let choice = node.choice.clone().unwrap_or(Choice {
+                        hold: do_hold,
+                        placement,
+                    });
+                    nodes.push(Node {
+                        game,
+                        choice: Some(choice),
+                        score,
+                        seen: seen + 1,
+                    });
+                }
             }
+        }
+        if nodes.is_empty() {
+            break;
+        }
+        // the first of the same scores is kept
+        nodes.sort_by(|a, b| b.score.total_cmp(&a.score));
+        nodes.truncate(lookahead.width.max(1));
+        beam = nodes;
+    }
+
+    if let Some(choice) = beam.into_iter().next().and_then(|node| node.choice) {
+        elite = choice;
+    }
+    elite
+}
 
-            // all move sideway
-            for dx in -4..=5 {
-                let mut game = game.clone();
-                // move process
-                let new_pos = Position {
-                    x: match game.pos.x as isize + dx {
-                        (..=0) => 0,
-                        x => x as usize,
-                    },
-                    y: game.pos.y,
-                };
-                move_block(&mut game, new_pos);
+// placements of the block in play from where it is
+// the search from the spawn is shorter above the stack
+fn reachable(game: &Game) -> Vec<Placement> {
+    let spawn = Piece::new(game.piece.kind);
+    let piece = &game.piece;
+    let max_gravity = is_max_gravity(game.level);
+    if (piece.pos.x, piece.pos.y) == (spawn.pos.x, spawn.pos.y)
+        && piece.rotation == spawn.rotation
+        && !max_gravity
+    {
+        placements(&game.field, piece.kind)
+    } else {
+        placements_from(&game.field, *piece, max_gravity)
+    }
+}
+
+// values of the features in the order of the genes with the block fixed
+// the stack is measured after the lines are erased
+fn features(game: &Game) -> [f64; GENOME_LEN] {
+    let field = &game.field;
+    let mut stack = *field;
+    erase_line(&mut stack);
+    [
+        erase_line_count(field) as f64,
+        field_hight_max(&stack) as f64,
+        diff_in_height(&stack) as f64,
+        dead_space_count(&stack) as f64,
+        column_heights(&stack).iter().sum::<usize>() as f64,
+        row_transitions(&stack) as f64,
+        column_transitions(&stack) as f64,
+        well_sums(&stack) as f64,
+        landing_height(&game.piece),
+        eroded_cells(field, &game.piece) as f64,
+    ]
+}
+
+// score of the field with the block fixed, before the lines are erased
+fn evaluate(game: &Game, weight: &GenoSeq) -> f64 {
+    // get input
+    let features = features(game);
+
+    // normalization
+    let mut line = normalization(features[GenomeKind::Line], 0.0, 4.0);
+    let mut height_max = 1.0 - normalization(features[GenomeKind::HeightMax], 0.0, 20.0);
+    let mut height_diff = 1.0 - normalization(features[GenomeKind::HeightDiff], 0.0, 200.0);
+    let mut dead_space = 1.0 - normalization(features[GenomeKind::DeadSpace], 0.0, 200.0);
+    let mut aggregate_height =
+        1.0 - normalization(features[GenomeKind::AggregateHeight], 0.0, 220.0);
+    let mut row_transitions = 1.0 - normalization(features[GenomeKind::RowTransitions], 0.0, 240.0);
+    let mut column_transitions =
+        1.0 - normalization(features[GenomeKind::ColumnTransitions], 0.0, 220.0);
+    let mut well_sums = 1.0 - normalization(features[GenomeKind::WellSums], 0.0, 400.0);
+    let mut landing_height = 1.0 - normalization(features[GenomeKind::LandingHeight], 0.0, 20.0);
+    let mut eroded_cells = normalization(features[GenomeKind::ErodedCells], 0.0, 16.0);
+
+    // weight
+    line *= weight[GenomeKind::Line] as f64;
+    height_max *= weight[GenomeKind::HeightMax] as f64;
+    height_diff *= weight[GenomeKind::HeightDiff] as f64;
+    dead_space *= weight[GenomeKind::DeadSpace] as f64;
+    aggregate_height *= weight[GenomeKind::AggregateHeight] as f64;
+    row_transitions *= weight[GenomeKind::RowTransitions] as f64;
+    column_transitions *= weight[GenomeKind::ColumnTransitions] as f64;
+    well_sums *= weight[GenomeKind::WellSums] as f64;
+    landing_height *= weight[GenomeKind::LandingHeight] as f64;
+    eroded_cells *= weight[GenomeKind::ErodedCells] as f64;
+
+    // eval input
+    line + height_max
+        + height_diff
+        + dead_space
+        + aggregate_height
+        + row_transitions
+        + column_transitions
+        + well_sums
+        + landing_height
+        + eroded_cells
+}
+
+// the bot pressing the keys of its choice one by one like a player
+pub struct AutoPlayer {
+    weights: GenoSeq,
+    lookahead: Lookahead,
+    // milliseconds between the inputs
+    delay: u64,
+    inputs: VecDeque<Action>,
+    // where the inputs lock the block
+    target: Option<Piece>,
+    // number of fixed blocks when the choice was made
+    planned: Option<usize>,
+    // game time of the next input
+    next_at: u64,
+    // game time the hard drop waits for
+    pub drop_at: u64,
+    // the key pressed and not released yet
+    pressed: Option<Action>,
+}
+
+impl AutoPlayer {
+    pub fn new(weights: GenoSeq, lookahead: Lookahead, delay: u64) -> AutoPlayer {
+        AutoPlayer {
+            weights,
+            lookahead,
+            delay,
+            inputs: VecDeque::new(),
+            target: None,
+            planned: None,
+            next_at: 0,
+            drop_at: 0,
+            pressed: None,
+        }
+    }
+
+    // the next key to press or release at the game time
+    // the key is released right after it is pressed
+    pub fn update(&mut self, game: &Game) -> Option<(Action, bool)> {
+        if let Some(action) = self.pressed.take() {
+            return Some((action, false));
+        }
+        // choose again for a new block, the inputs left are dropped
+        if self.planned != Some(game.stats.pieces) {
+            self.planned = Some(game.stats.pieces);
+            self.plan(game);
+            self.next_at = game.time + self.delay;
+        }
+        if game.time < self.next_at {
+            return None;
+        }
+        if self.inputs.front() == Some(&Action::HardDrop) && game.time < self.drop_at {
+            return None;
+        }
+        // the gravity or the soft drop took the block off the path
+        if !self.on_track(game) {
+            self.plan(game);
+        }
+        let action = self.inputs.pop_front()?;
+        // one input each millisecond at most
+        self.next_at = game.time + self.delay.max(1);
+        self.pressed = Some(action);
+        Some((action, true))
+    }
+
+    fn plan(&mut self, game: &Game) {
+        let choice = search(game, &self.weights, self.lookahead);
+        self.inputs = choice.inputs().into();
+        self.target = Some(choice.placement.piece);
+    }
+
+    // the inputs left still lock the block at the target
+    fn on_track(&self, game: &Game) -> bool {
+        let Some(target) = self.target else {
+            return true;
+        };
+        let mut game = game.clone();
+        let mut controller = Controller::new(Handling::default());
+        for &action in self.inputs.iter().take_while(|&&a| a != Action::HardDrop) {
+            controller.input(&mut game, action, true).ok();
+            controller.input(&mut game, action, false).ok();
+            if is_max_gravity(game.level) {
                 hard_drop(&mut game);
-                fix_block(&mut game);
-                
-                // get input
-                let line = erase_line_count(&game.field);
-                let height_max = field_hight_max(&game.field);
-                let height_diff = diff_in_height(&game.field);
-                let dead_space = dead_space_count(&game.field);
-
-                // normalization
-                let mut line = normalization(line as f64, 0.0, 4.0);
-                let mut height_max = 1.0 - normalization(height_max as f64, 0.0, 20.0);
-                let mut height_diff = 1.0 - normalization(height_diff as f64, 0.0, 200.0);
-                let mut dead_space = 1.0 - normalization(dead_space as f64, 0.0, 200.0);
-
-                // weight
-                line *= weight[GenomeKind::Line] as f64;
-                height_max *= weight[GenomeKind::HeightMax] as f64;
-                height_diff *= weight[GenomeKind::HeightDiff] as f64;
-                dead_space *= weight[GenomeKind::DeadSpace] as f64;
-
-                // eval input
-                let score = line + height_max + height_diff + dead_space;
-                if elite.1 < score {
-                    // save best
-                    elite.0 = game.clone();
-                    elite.1 = score;
-                }
             }
         }
+        hard_drop(&mut game);
+        let mut landed = cells(&game.piece);
+        let mut expected = cells(&target);
+        landed.sort();
+        expected.sort();
+        landed == expected
     }
-    elite.0
 }
 
 // get count of can erase line
@@ -87,16 +338,8 @@ fn erase_line_count(field: &Field) -> usize {
 }
 
 // get hight of the highest block in the field
-#[allow(clippy::needless_range_loop)]
 fn field_hight_max(field: &Field) -> usize {
-    for y in 1..FIELD_HEIGHT - 2 {
-        for x in 2..FIELD_WIDTH - 2 {
-            if field[y][x] != block_kind::NONE {
-                return FIELD_HEIGHT - y - 1;
-            }
-        }
-    }
-    unreachable!();
+    column_heights(field).into_iter().max().unwrap_or(0)
 }
 
 // normalization
@@ -105,27 +348,15 @@ fn normalization(value: f64, min: f64, max: f64) -> f64 {
 }
 
 // get difference in field elevation
-#[allow(clippy::needless_range_loop)]
 pub fn diff_in_height(field: &Field) -> usize {
-    let mut diff = 0;
-    let mut top = [0; FIELD_WIDTH - 4];
-    // find the height at the top of each column
-    for x in 2..FIELD_WIDTH - 2 {
-        for y in 1..FIELD_HEIGHT - 2 {
-            if field[y][x] != block_kind::NONE {
-                top[x - 2] = FIELD_HEIGHT - y - 1;
-                break;
-            }
-        }
-    }
-    // sum side difference
-    for i in 0..FIELD_WIDTH - 4 - 1 {
-        diff += top[i].abs_diff(top[i + 1]);
-    }
-    diff
+    column_heights(field)
+        .windows(2)
+        .map(|w| w[0].abs_diff(w[1]))
+        .sum()
 }
 
 // get number of dead spaces
+#[allow(clippy::needless_range_loop)]
 pub fn dead_space_count(field: &Field) -> usize {
     let mut count = 0;
     for y in (1..FIELD_HEIGHT - 2).rev() {
@@ -142,3 +373,225 @@ pub fn dead_space_count(field: &Field) -> usize {
     }
     count
 }
+
+// height of each column of the playfield
+fn column_heights(field: &Field) -> [usize; PLAYFIELD_WIDTH] {
+    let mut heights = [0; PLAYFIELD_WIDTH];
+    for (i, height) in heights.iter_mut().enumerate() {
+        *height = (0..FIELD_HEIGHT - 2)
+            .find(|&y| field[y][i + 2] != block_kind::NONE)
+            .map_or(0, |y| FIELD_HEIGHT - 2 - y);
+    }
+    heights
+}
+
+// changes between filled and empty cells along the rows with blocks
+// the walls count as filled
+fn row_transitions(field: &Field) -> usize {
+    field[..FIELD_HEIGHT - 2]
+        .iter()
+        .filter(|row| {
+            row[2..FIELD_WIDTH - 2]
+                .iter()
+                .any(|&c| c != block_kind::NONE)
+        })
+        .map(|row| {
+            row[1..FIELD_WIDTH - 1]
+                .windows(2)
+                .filter(|w| (w[0] == block_kind::NONE) != (w[1] == block_kind::NONE))
+                .count()
+        })
+        .sum()
+}
+
+// changes between filled and empty cells down the columns
+// the floor counts as filled
+fn column_transitions(field: &Field) -> usize {
+    (2..FIELD_WIDTH - 2)
+        .map(|x| {
+            (0..FIELD_HEIGHT - 2)
+                .filter(|&y| {
+                    (field[y][x] == block_kind::NONE) != (field[y + 1][x] == block_kind::NONE)
+                })
+                .count()
+        })
+        .sum()
+}
+
+// sum of 1 + 2 + ... + depth of each well,
+// the empty cells with both sides filled stacked in a column
+fn well_sums(field: &Field) -> usize {
+    let mut sum = 0;
+    for x in 2..FIELD_WIDTH - 2 {
+        let mut depth = 0;
+        for row in &field[..FIELD_HEIGHT - 2] {
+            if row[x] == block_kind::NONE
+                && row[x - 1] != block_kind::NONE
+                && row[x + 1] != block_kind::NONE
+            {
+                depth += 1;
+                sum += depth;
+            } else {
+                depth = 0;
+            }
+        }
+    }
+    sum
+}
+
+// height of the middle of the block from the floor
+fn landing_height(piece: &Piece) -> f64 {
+    let cells = cells(piece);
+    let top = cells.iter().map(|c| FIELD_HEIGHT - 2 - c.1).max().unwrap();
+    let bottom = cells.iter().map(|c| FIELD_HEIGHT - 2 - c.1).min().unwrap();
+    (top + bottom) as f64 / 2.0
+}
+
+// number of lines erased times the cells of the block erased with them
+fn eroded_cells(field: &Field, piece: &Piece) -> usize {
+    let full = |y: usize| {
+        field[y][2..FIELD_WIDTH - 2]
+            .iter()
+            .all(|&c| c != block_kind::NONE)
+    };
+    let lines = (0..FIELD_HEIGHT - 2).filter(|&y| full(y)).count();
+    let cells = cells(piece).iter().filter(|c| full(c.1)).count();
+    lines * cells
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blocks::Blockkind;
+    use crate::input::{Controller, Handling};
+    use crate::randomizer::Bag7;
+
+    #[test]
+    fn test_lookahead() {
+        let mut game = Game::new(0, Box::<Bag7>::default());
+        // a well of 2 columns the T block spoils but the Z block after it fills
+        let heights = [2, 2, 2, 2, 2, 2, 0, 0, 3, 3, 2];
+        for (x, height) in (2..).zip(heights) {
+            for y in FIELD_HEIGHT - 2 - height..FIELD_HEIGHT - 2 {
+                game.field[y][x] = block_kind::GARBAGE;
+            }
+        }
+        game.piece = Piece::new(Blockkind::T);
+        game.next = [Blockkind::Z, Blockkind::O, Blockkind::O, Blockkind::O]
+            .into_iter()
+            .collect();
+
+        let lines = |lookahead| {
+            let mut game = game.clone();
+            for _ in 0..2 {
+                let choice = search(&game, &WEIGHTS, lookahead);
+                place(&mut game, &choice);
+                landing(&mut game).unwrap();
+            }
+            game.line
+        };
+        assert_eq!(lines(Lookahead::GREEDY), 1);
+        assert_eq!(lines(Lookahead { depth: 2, width: 8 }), 2);
+    }
+
+    #[test]
+    fn test_features() {
+        let mut game = Game::new(0, Box::<Bag7>::default());
+        let bottom = FIELD_HEIGHT - 3;
+        // a column of 2, an empty column and a column of 2 with a hole under it
+        for (x, y) in [(2, bottom - 1), (2, bottom), (4, bottom - 1)] {
+            game.field[y][x] = block_kind::GARBAGE;
+        }
+        let field = &game.field;
+        assert_eq!(column_heights(field)[..4], [2, 0, 2, 0]);
+        assert_eq!(dead_space_count(field), 1);
+        assert_eq!(row_transitions(field), 4 + 2);
+        assert_eq!(column_transitions(field), 1 + 1 + 3 + 8);
+        assert_eq!(well_sums(field), 1);
+
+        // an O block erasing 2 lines at the right under the same blocks
+        let mut game = Game::new(0, Box::<Bag7>::default());
+        for y in bottom - 1..=bottom {
+            for x in 2..FIELD_WIDTH - 4 {
+                game.field[y][x] = block_kind::GARBAGE;
+            }
+        }
+        for (x, y) in [(2, bottom - 3), (2, bottom - 2), (4, bottom - 3)] {
+            game.field[y][x] = block_kind::GARBAGE;
+        }
+        game.piece = Piece {
+            pos: Position {
+                x: FIELD_WIDTH - 5,
+                y: bottom - 1,
+            },
+            ..Piece::new(Blockkind::O)
+        };
+        fix_block(&mut game);
+        assert_eq!(landing_height(&game.piece), 1.5);
+        assert_eq!(eroded_cells(&game.field, &game.piece), 2 * 4);
+        // the stack is the first field after the lines are erased
+        let features = features(&game);
+        assert_eq!(features[GenomeKind::Line], 2.0);
+        assert_eq!(features[GenomeKind::HeightMax], 2.0);
+        assert_eq!(features[GenomeKind::HeightDiff], 2.0 * 3.0);
+        assert_eq!(features[GenomeKind::DeadSpace], 1.0);
+        assert_eq!(features[GenomeKind::AggregateHeight], 4.0);
+        assert_eq!(features[GenomeKind::RowTransitions], 4.0 + 2.0);
+        assert_eq!(
+            features[GenomeKind::ColumnTransitions],
+            1.0 + 1.0 + 3.0 + 8.0
+        );
+        assert_eq!(features[GenomeKind::WellSums], 1.0);
+        assert_eq!(features[GenomeKind::LandingHeight], 1.5);
+        assert_eq!(features[GenomeKind::ErodedCells], 8.0);
+    }
+
+    #[test]
+    fn test_auto_player() {
+        let mut game = Game::new(1, Box::<Bag7>::default());
+        let mut controller = Controller::new(Handling::default());
+        let mut bot = AutoPlayer::new(WEIGHTS, Lookahead::GREEDY, 5);
+        for _ in 0..10_000 {
+            while let Some((action, pressed)) = bot.update(&game) {
+                controller.input(&mut game, action, pressed).unwrap();
+            }
+            controller.update(&mut game);
+            update(&mut game).unwrap();
+        }
+        // the blocks are placed by the keys, not by the gravity
+        assert!(game.stats.pieces > 50);
+        assert!(game.line > 10);
+    }
+
+    #[test]
+    fn test_auto_player_gravity() {
+        let mut game = Game::new(1, Box::<Bag7>::default());
+        // 20G
+        game.level = 20;
+        assert!(is_max_gravity(game.level));
+        let mut controller = Controller::new(Handling::default());
+        let mut bot = AutoPlayer::new(WEIGHTS, Lookahead::GREEDY, 5);
+        for _ in 0..10_000 {
+            let (pieces, field) = (game.stats.pieces, game.field);
+            let mut target = None;
+            while let Some((action, pressed)) = bot.update(&game) {
+                if action == Action::HardDrop && pressed {
+                    target = bot.target;
+                }
+                controller.input(&mut game, action, pressed).unwrap();
+            }
+            let target = target.or(bot.target);
+            controller.update(&mut game);
+            update(&mut game).unwrap();
+            // the block is locked where the bot chose
+            if game.stats.pieces != pieces && game.last_clear.is_some_and(|c| c.line == 0) {
+                let target = target.unwrap();
+                assert!(cells(&target)
+                    .iter()
+                    .all(|&(x, y)| field[y][x] == block_kind::NONE
+                        && game.field[y][x] != block_kind::NONE));
+            }
+        }
+        assert!(game.stats.pieces > 50);
+    }
+}
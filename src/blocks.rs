@@ -2,11 +2,11 @@ use block_kind::{I, J, L, O, S, T, Z};
 use rand::{
     distributions::{Distribution, Standard},
     seq::SliceRandom,
-    thread_rng, Rng,
+    Rng,
 };
 
 const BLOCK_KIND_MAX: usize = 7;
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub enum Blockkind {
     I,
     O,
@@ -17,6 +17,16 @@ pub enum Blockkind {
     T,
 }
 
+pub const BLOCK_KINDS: [Blockkind; BLOCK_KIND_MAX] = [
+    Blockkind::I,
+    Blockkind::O,
+    Blockkind::S,
+    Blockkind::Z,
+    Blockkind::J,
+    Blockkind::L,
+    Blockkind::T,
+];
+
 pub type BlockColor = usize;
 
 pub mod block_kind {
@@ -30,9 +40,10 @@ pub mod block_kind {
     pub const J: super::BlockColor = 7;
     pub const L: super::BlockColor = 8;
     pub const T: super::BlockColor = 9;
+    pub const GARBAGE: super::BlockColor = 10;
 }
 
-pub const COLOR_TABLE: [&str; 10] = [
+pub const COLOR_TABLE: [&str; 11] = [
     "\x1b[48;2;000;000;000m  ", // 何もなし
     "\x1b[48;2;127;127;127m__", // 壁
     "\x1b[48;2;000;000;000m[]", // ゴースト
@@ -43,6 +54,7 @@ pub const COLOR_TABLE: [&str; 10] = [
     "\x1b[48;2;255;000;255m__", // J
     "\x1b[48;2;255;127;000m__", // L
     "\x1b[48;2;255;255;000m__", // T
+    "\x1b[48;2;063;063;063m__", // おじゃま
 ];
 
 impl Distribution<Blockkind> for Standard {
@@ -61,34 +73,154 @@ impl Distribution<Blockkind> for Standard {
 
 pub type BlockShape = [[usize; 4]; 4];
 
+// spawn orientation of each block
+// I block rotates in the whole 4x4 box, O block does not rotate,
+// and the others rotate in the upper left 3x3 box
 pub const BLOCKS: [BlockShape; BLOCK_KIND_MAX] = [
     // I block
-    [[0, 0, 0, 0], [0, 0, 0, 0], [I, I, I, I], [0, 0, 0, 0]],
+    [[0, 0, 0, 0], [I, I, I, I], [0, 0, 0, 0], [0, 0, 0, 0]],
     // O block
-    [[0, 0, 0, 0], [0, O, O, 0], [0, O, O, 0], [0, 0, 0, 0]],
+    [[0, O, O, 0], [0, O, O, 0], [0, 0, 0, 0], [0, 0, 0, 0]],
     // S block
-    [[0, 0, 0, 0], [0, S, S, 0], [S, S, 0, 0], [0, 0, 0, 0]],
+    [[0, S, S, 0], [S, S, 0, 0], [0, 0, 0, 0], [0, 0, 0, 0]],
     // Z block
-    [[0, 0, 0, 0], [Z, Z, 0, 0], [0, Z, Z, 0], [0, 0, 0, 0]],
+    [[Z, Z, 0, 0], [0, Z, Z, 0], [0, 0, 0, 0], [0, 0, 0, 0]],
     // J block
-    [[0, 0, 0, 0], [J, 0, 0, 0], [J, J, J, 0], [0, 0, 0, 0]],
+    [[J, 0, 0, 0], [J, J, J, 0], [0, 0, 0, 0], [0, 0, 0, 0]],
     // L block
-    [[0, 0, 0, 0], [0, 0, L, 0], [L, L, L, 0], [0, 0, 0, 0]],
+    [[0, 0, L, 0], [L, L, L, 0], [0, 0, 0, 0], [0, 0, 0, 0]],
     // T block
-    [[0, 0, 0, 0], [0, T, 0, 0], [T, T, T, 0], [0, 0, 0, 0]],
+    [[0, T, 0, 0], [T, T, T, 0], [0, 0, 0, 0], [0, 0, 0, 0]],
+];
+
+// shapes of each block in each rotation state, indexed by [block][rotation]
+pub const SHAPES: [[BlockShape; 4]; BLOCK_KIND_MAX] = gen_shapes();
+
+const fn gen_shapes() -> [[BlockShape; 4]; BLOCK_KIND_MAX] {
+    let mut shapes = [[[[0; 4]; 4]; 4]; BLOCK_KIND_MAX];
+    let mut kind = 0;
+    while kind < BLOCK_KIND_MAX {
+        // size of the box the block rotates in
+        let size = if kind == Blockkind::I as usize {
+            4
+        } else if kind == Blockkind::O as usize {
+            0
+        } else {
+            3
+        };
+        shapes[kind][0] = BLOCKS[kind];
+        let mut rotation = 1;
+        while rotation < 4 {
+            let prev = shapes[kind][rotation - 1];
+            // O block keeps its shape
+            let mut shape = prev;
+            let mut y = 0;
+            while y < size {
+                let mut x = 0;
+                while x < size {
+                    shape[y][x] = prev[size - 1 - x][y];
+                    x += 1;
+                }
+                y += 1;
+            }
+            shapes[kind][rotation] = shape;
+            rotation += 1;
+        }
+        kind += 1;
+    }
+    shapes
+}
+
+// rotation state of a block (0, R, 2, L)
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Rotation {
+    Spawn,
+    Right,
+    Reverse,
+    Left,
+}
+
+pub const ROTATIONS: [Rotation; 4] = [
+    Rotation::Spawn,
+    Rotation::Right,
+    Rotation::Reverse,
+    Rotation::Left,
 ];
 
-pub fn gen_block_7() -> [BlockShape; BLOCK_KIND_MAX] {
-    let mut rng = thread_rng();
-    let mut que = [
-        Blockkind::I,
-        Blockkind::O,
-        Blockkind::S,
-        Blockkind::Z,
-        Blockkind::J,
-        Blockkind::L,
-        Blockkind::T,
-    ];
-    que.shuffle(&mut rng);
-    que.map(|block| BLOCKS[block as usize])
+impl Rotation {
+    pub fn cw(self) -> Rotation {
+        match self {
+            Rotation::Spawn => Rotation::Right,
+            Rotation::Right => Rotation::Reverse,
+            Rotation::Reverse => Rotation::Left,
+            Rotation::Left => Rotation::Spawn,
+        }
+    }
+
+    pub fn ccw(self) -> Rotation {
+        match self {
+            Rotation::Spawn => Rotation::Left,
+            Rotation::Right => Rotation::Spawn,
+            Rotation::Reverse => Rotation::Right,
+            Rotation::Left => Rotation::Reverse,
+        }
+    }
+}
+
+// SRS wall kick offsets, indexed by [from rotation][0: cw, 1: ccw]
+// (x, y) with the y axis pointing up as in the guideline tables
+pub type KickTable = [[[(isize, isize); 5]; 2]; 4];
+
+const JLSTZ_KICKS: KickTable = [
+    [
+        [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)], // 0 -> R
+        [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],    // 0 -> L
+    ],
+    [
+        [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)], // R -> 2
+        [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)], // R -> 0
+    ],
+    [
+        [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],    // 2 -> L
+        [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)], // 2 -> R
+    ],
+    [
+        [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)], // L -> 0
+        [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)], // L -> 2
+    ],
+];
+
+const I_KICKS: KickTable = [
+    [
+        [(0, 0), (-2, 0), (1, 0), (-2, -1), (1, 2)], // 0 -> R
+        [(0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)], // 0 -> L
+    ],
+    [
+        [(0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)], // R -> 2
+        [(0, 0), (2, 0), (-1, 0), (2, 1), (-1, -2)], // R -> 0
+    ],
+    [
+        [(0, 0), (2, 0), (-1, 0), (2, 1), (-1, -2)], // 2 -> L
+        [(0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)], // 2 -> R
+    ],
+    [
+        [(0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)], // L -> 0
+        [(0, 0), (-2, 0), (1, 0), (-2, -1), (1, 2)], // L -> 2
+    ],
+];
+
+const O_KICKS: KickTable = [[[(0, 0); 5]; 2]; 4];
+
+pub fn kick_table(kind: Blockkind) -> &'static KickTable {
+    match kind {
+        Blockkind::I => &I_KICKS,
+        Blockkind::O => &O_KICKS,
+        _ => &JLSTZ_KICKS,
+    }
+}
+
+pub fn gen_block_7<R: Rng + ?Sized>(rng: &mut R) -> [Blockkind; BLOCK_KIND_MAX] {
+    let mut que = BLOCK_KINDS;
+    que.shuffle(rng);
+    que
 }
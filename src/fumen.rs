@@ -0,0 +1,339 @@
+use crate::blocks::{block_kind, BlockColor, Blockkind, Rotation, SHAPES};
+use crate::game::*;
+
+// fumen v115, the field editor shared as "v115@..." strings
+//
+// the field of fumen is 10 wide and 23 high with a garbage row below it,
+// and this field is 11 wide and 20 high:
+// the rightmost column of this field is filled on the rows with any block when decoded
+// and dropped when encoded, rows above the 20th are dropped too
+
+const PREFIX: &str = "v115@";
+const ENCODE_TABLE: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const FUMEN_WIDTH: usize = 10;
+// rows above the garbage row
+const FUMEN_TOP: usize = 23;
+const FUMEN_BLOCKS: usize = FUMEN_WIDTH * (FUMEN_TOP + 1);
+// rows of this field
+const ROWS: usize = FIELD_HEIGHT - 2;
+
+// colors of fumen: empty, I, L, O, Z, T, J, S, gray
+const COLORS: [BlockColor; 9] = [
+    block_kind::NONE,
+    block_kind::I,
+    block_kind::L,
+    block_kind::O,
+    block_kind::Z,
+    block_kind::T,
+    block_kind::J,
+    block_kind::S,
+    block_kind::GARBAGE,
+];
+const KINDS: [Option<Blockkind>; 8] = [
+    None,
+    Some(Blockkind::I),
+    Some(Blockkind::L),
+    Some(Blockkind::O),
+    Some(Blockkind::Z),
+    Some(Blockkind::T),
+    Some(Blockkind::J),
+    Some(Blockkind::S),
+];
+const ROTATIONS: [Rotation; 4] = [
+    Rotation::Reverse,
+    Rotation::Right,
+    Rotation::Spawn,
+    Rotation::Left,
+];
+
+// the first page of a fumen
+pub struct Fumen {
+    pub field: Field,
+    pub piece: Option<Piece>,
+}
+
+pub fn decode(text: &str) -> Option<Fumen> {
+    let data = &text[text.find(PREFIX)? + PREFIX.len()..];
+    let mut values = data
+        .bytes()
+        .filter(|&c| c != b'?')
+        .map(|c| ENCODE_TABLE.iter().position(|&e| e == c));
+    let mut poll = |n: usize| -> Option<usize> {
+        let mut value = 0;
+        for i in 0..n {
+            value += values.next()??.checked_mul(64usize.pow(i as u32))?;
+        }
+        Some(value)
+    };
+
+    // differences from the empty field in runs
+    let mut cells = [0; FUMEN_BLOCKS];
+    let mut index = 0;
+    while index < FUMEN_BLOCKS {
+        let value = poll(2)?;
+        let (diff, count) = (value / FUMEN_BLOCKS, value % FUMEN_BLOCKS + 1);
+        let color = diff.checked_sub(8)?;
+        cells.get_mut(index..index + count)?.fill(color);
+        index += count;
+        if index == FUMEN_BLOCKS && diff == 8 && count == FUMEN_BLOCKS {
+            // repeat count of the empty pages
+            poll(1)?;
+        }
+    }
+
+    let mut field = empty_field();
+    for (y, row) in field[..ROWS].iter_mut().enumerate() {
+        let fumen_row = &cells[(FUMEN_TOP - ROWS + y) * FUMEN_WIDTH..][..FUMEN_WIDTH];
+        for (cell, &color) in row[2..].iter_mut().zip(fumen_row) {
+            *cell = *COLORS.get(color)?;
+        }
+        if fumen_row.iter().any(|&color| color != 0) {
+            row[FIELD_WIDTH - 3] = block_kind::GARBAGE;
+        }
+    }
+    // the rows full in fumen are cleared as the extra column never opens
+    erase_line(&mut field);
+
+    let mut action = poll(3)?;
+    let kind = KINDS[action % 8];
+    action /= 8;
+    let rotation = ROTATIONS[action % 4];
+    action /= 4;
+    let location = action % FUMEN_BLOCKS;
+    // the piece above this field is dropped
+    let piece = kind.and_then(|kind| {
+        piece_at(
+            kind,
+            rotation,
+            location % FUMEN_WIDTH,
+            location / FUMEN_WIDTH,
+        )
+    });
+    Some(Fumen { field, piece })
+}
+
+pub fn encode(field: &Field, piece: Option<&Piece>) -> String {
+    let mut cells = [0; FUMEN_BLOCKS];
+    for (y, row) in field[..ROWS].iter().enumerate() {
+        let fumen_row = &mut cells[(FUMEN_TOP - ROWS + y) * FUMEN_WIDTH..][..FUMEN_WIDTH];
+        for (cell, color) in fumen_row.iter_mut().zip(&row[2..]) {
+            *cell = COLORS.iter().position(|c| c == color).unwrap_or(8);
+        }
+    }
+
+    let mut values = vec![];
+    let mut push = |value: usize, n: usize| {
+        let mut value = value;
+        for _ in 0..n {
+            values.push(ENCODE_TABLE[value % 64]);
+            value /= 64;
+        }
+    };
+    let mut start = 0;
+    for index in 1..=FUMEN_BLOCKS {
+        if index == FUMEN_BLOCKS || cells[index] != cells[start] {
+            push((cells[start] + 8) * FUMEN_BLOCKS + index - start - 1, 2);
+            start = index;
+        }
+    }
+    if cells.iter().all(|&color| color == 0) {
+        // no empty pages follow
+        push(0, 1);
+    }
+
+    // the piece out of the fumen field is not encoded
+    let (kind, rotation, location) = piece
+        .and_then(location_of)
+        .map_or((0, 0, 0), |(kind, rotation, x, y)| {
+            (kind, rotation, y * FUMEN_WIDTH + x)
+        });
+    // guideline colors on the first page, no comments and the piece locked
+    let color = 1;
+    let action = ((color * 4) * FUMEN_BLOCKS + location) * 32 + rotation * 8 + kind;
+    push(action, 3);
+
+    // "?" after the first 42 characters and every 47 characters
+    let mut text = PREFIX.to_string();
+    for (i, &c) in values.iter().enumerate() {
+        if i >= 42 && (i - 42) % 47 == 0 {
+            text.push('?');
+        }
+        text.push(c as char);
+    }
+    text
+}
+
+// cells of the block around the center of SRS, with the y axis up
+fn fumen_cells(kind: Blockkind, rotation: Rotation) -> [(isize, isize); 4] {
+    let cells = match kind {
+        Blockkind::I => [(0, 0), (-1, 0), (1, 0), (2, 0)],
+        Blockkind::T => [(0, 0), (-1, 0), (1, 0), (0, 1)],
+        Blockkind::O => [(0, 0), (1, 0), (0, 1), (1, 1)],
+        Blockkind::L => [(0, 0), (-1, 0), (1, 0), (1, 1)],
+        Blockkind::J => [(0, 0), (-1, 0), (1, 0), (-1, 1)],
+        Blockkind::S => [(0, 0), (-1, 0), (0, 1), (1, 1)],
+        Blockkind::Z => [(0, 0), (1, 0), (0, 1), (-1, 1)],
+    };
+    cells.map(|(x, y)| match rotation {
+        Rotation::Spawn => (x, y),
+        Rotation::Right => (y, -x),
+        Rotation::Reverse => (-x, -y),
+        Rotation::Left => (-y, x),
+    })
+}
+
+// center of the block in this field from the center in fumen, with the y axis up
+// fumen turns I, O, S and Z around other centers than SRS
+fn center_offset(kind: Blockkind, rotation: Rotation) -> (isize, isize) {
+    match (kind, rotation) {
+        (Blockkind::O, Rotation::Spawn) => (0, -1),
+        (Blockkind::O, Rotation::Reverse) => (1, 0),
+        (Blockkind::O, Rotation::Left) => (1, -1),
+        (Blockkind::I, Rotation::Reverse) => (1, 0),
+        (Blockkind::I, Rotation::Left) => (0, -1),
+        (Blockkind::S, Rotation::Spawn) => (0, -1),
+        (Blockkind::S, Rotation::Right) => (-1, 0),
+        (Blockkind::Z, Rotation::Spawn) => (0, -1),
+        (Blockkind::Z, Rotation::Left) => (1, 0),
+        _ => (0, 0),
+    }
+}
+
+// top left of the cells of the block in this field, y axis down
+fn shape_min(kind: Blockkind, rotation: Rotation) -> (usize, usize) {
+    let shape = &SHAPES[kind as usize][rotation as usize];
+    let cells = (0..4).flat_map(|y| (0..4).map(move |x| (x, y)));
+    let filled: Vec<_> = cells
+        .filter(|&(x, y)| shape[y][x] != block_kind::NONE)
+        .collect();
+    (
+        filled.iter().map(|c| c.0).min().unwrap_or(0),
+        filled.iter().map(|c| c.1).min().unwrap_or(0),
+    )
+}
+
+// the piece of this field from the center in fumen
+// x from the left, row from the top of fumen
+fn piece_at(kind: Blockkind, rotation: Rotation, x: usize, row: usize) -> Option<Piece> {
+    let cells = fumen_cells(kind, rotation);
+    let (dx, dy) = center_offset(kind, rotation);
+    let (x, row) = (x as isize + dx, row as isize - dy);
+    // left and top of the cells in this field
+    let left = x + cells.iter().map(|c| c.0).min()?;
+    let top = row - cells.iter().map(|c| c.1).max()? - (FUMEN_TOP - ROWS) as isize;
+    let (min_x, min_y) = shape_min(kind, rotation);
+    let pos = Position {
+        x: usize::try_from(left + 2 - min_x as isize).ok()?,
+        y: usize::try_from(top - min_y as isize).ok()?,
+    };
+    Some(Piece {
+        kind,
+        rotation,
+        pos,
+    })
+}
+
+// kind, rotation and center of the piece in fumen
+fn location_of(piece: &Piece) -> Option<(usize, usize, usize, usize)> {
+    let cells = fumen_cells(piece.kind, piece.rotation);
+    let (min_x, min_y) = shape_min(piece.kind, piece.rotation);
+    let left = (piece.pos.x + min_x) as isize - 2;
+    let top = (piece.pos.y + min_y) as isize + (FUMEN_TOP - ROWS) as isize;
+    let x = left - cells.iter().map(|c| c.0).min()?;
+    let row = top + cells.iter().map(|c| c.1).max()?;
+    let right = x + cells.iter().map(|c| c.0).max()?;
+    let (dx, dy) = center_offset(piece.kind, piece.rotation);
+    let (x, row) = (x - dx, row + dy);
+    if left < 0 || right >= FUMEN_WIDTH as isize || x < 0 || row < 0 {
+        return None;
+    }
+    let kind = KINDS.iter().position(|&k| k == Some(piece.kind))?;
+    let rotation = ROTATIONS.iter().position(|&r| r == piece.rotation)?;
+    Some((kind, rotation, x as usize, row as usize))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fumen() {
+        // empty field without a piece
+        let fumen = decode("https://fumen.zui.jp/?v115@vhAAgH").unwrap();
+        assert!(fumen.piece.is_none());
+        assert_eq!(garbage_rows(&fumen.field), 0);
+        assert_eq!(encode(&fumen.field, None), "v115@vhAAgH");
+
+        let mut field = empty_field();
+        for x in (2..FIELD_WIDTH - 2).filter(|&x| x != 4) {
+            field[ROWS - 1][x] = block_kind::GARBAGE;
+            field[ROWS - 2][x] = block_kind::I;
+        }
+        for rotation in ROTATIONS {
+            for kind in crate::blocks::BLOCK_KINDS {
+                let mut piece = Piece::new(kind);
+                piece.rotation = rotation;
+                let text = encode(&field, Some(&piece));
+                let fumen = decode(&text).unwrap();
+                let decoded = fumen.piece.unwrap();
+                assert!(decoded.kind == kind && decoded.rotation == rotation);
+                assert_eq!((decoded.pos.x, decoded.pos.y), (piece.pos.x, piece.pos.y));
+                assert_eq!(fumen.field[ROWS - 1], field[ROWS - 1]);
+                assert_eq!(fumen.field[ROWS - 2][2], block_kind::I);
+            }
+        }
+    }
+
+    #[test]
+    fn test_fumen_center() {
+        // blocks at the bottom left in each rotation, placed by the centers of fumen
+        let tests = [
+            ("v115@vhAxOJ", [(2, 19), (3, 19), (4, 19), (5, 19)]),
+            ("v115@vhAJEJ", [(2, 16), (2, 17), (2, 18), (2, 19)]),
+            ("v115@vhAhOJ", [(2, 19), (3, 19), (4, 19), (5, 19)]),
+            ("v115@vhAZEJ", [(2, 16), (2, 17), (2, 18), (2, 19)]),
+            ("v115@vhATJJ", [(2, 18), (2, 19), (3, 18), (3, 19)]),
+            ("v115@vhALJJ", [(2, 18), (2, 19), (3, 18), (3, 19)]),
+            ("v115@vhADJJ", [(2, 18), (2, 19), (3, 18), (3, 19)]),
+            ("v115@vhAbJJ", [(2, 18), (2, 19), (3, 18), (3, 19)]),
+            ("v115@vhA3JJ", [(2, 19), (3, 18), (3, 19), (4, 18)]),
+            ("v115@vhAvJJ", [(2, 17), (2, 18), (3, 18), (3, 19)]),
+            ("v115@vhAnJJ", [(2, 19), (3, 18), (3, 19), (4, 18)]),
+            ("v115@vhA/JJ", [(2, 17), (2, 18), (3, 18), (3, 19)]),
+            ("v115@vhA0JJ", [(2, 18), (3, 18), (3, 19), (4, 19)]),
+            ("v115@vhAMJJ", [(2, 18), (2, 19), (3, 17), (3, 18)]),
+            ("v115@vhAkJJ", [(2, 18), (3, 18), (3, 19), (4, 19)]),
+            ("v115@vhAcJJ", [(2, 18), (2, 19), (3, 17), (3, 18)]),
+        ];
+        for (text, expected) in tests {
+            let fumen = decode(text).unwrap();
+            let piece = fumen.piece.unwrap();
+            let mut cells = crate::movegen::cells(&piece);
+            cells.sort();
+            assert_eq!(cells, expected, "{text}");
+            assert_eq!(encode(&fumen.field, Some(&piece)), text);
+        }
+    }
+
+    #[test]
+    fn test_fumen_full_rows() {
+        // 5 rows full in the 10 columns
+        let fumen = decode("v115@zghiJeAgH").unwrap();
+        assert_eq!(garbage_rows(&fumen.field), 0);
+        assert!(is_perfect_clear(&fumen.field));
+        let mut game = Game::new(0, Box::<crate::randomizer::Bag7>::default());
+        game.load_fumen(&fumen);
+        hard_drop(&mut game);
+        assert!(landing(&mut game).is_ok());
+
+        // the full rows left in the field are cleared by the next lock
+        let mut game = Game::new(0, Box::<crate::randomizer::Bag7>::default());
+        for row in &mut game.field[FIELD_HEIGHT - 7..FIELD_HEIGHT - 2] {
+            row[2..FIELD_WIDTH - 2].fill(block_kind::GARBAGE);
+        }
+        hard_drop(&mut game);
+        assert!(landing(&mut game).is_ok());
+        assert_eq!(game.line, 5);
+        assert_eq!(game.last_clear.unwrap().name(), Some("TETRIS"));
+    }
+}
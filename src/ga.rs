@@ -1,5 +1,6 @@
-use crate::ai::eval;
+use crate::ai::{eval, place};
 use crate::game::*;
+use crate::play::Settings;
 use rand::{
     distributions::{Distribution, Standard},
     seq::SliceRandom,
@@ -35,14 +36,26 @@ const _: () = assert!(CROSSOVER_LEN + MUTATION_LEN + SELECTION_LEN == POPULATION
 pub enum GenomeKind {
     Line,
     HeightMax,
+    // bumpiness, the differences between the heights of the columns
     HeightDiff,
+    // holes, the empty cells under the blocks
     DeadSpace,
+    AggregateHeight,
+    RowTransitions,
+    ColumnTransitions,
+    WellSums,
+    LandingHeight,
+    ErodedCells,
 }
 
+// number of genes
+pub const GENOME_LEN: usize = 10;
+
 // gene sequence
-pub type GenoSeq = [u8; 4];
-impl Index<GenomeKind> for GenoSeq {
-    type Output = u8;
+pub type GenoSeq = [u8; GENOME_LEN];
+// the genes and the features they weigh are in the same order
+impl<T> Index<GenomeKind> for [T; GENOME_LEN] {
+    type Output = T;
     fn index(&self, kind: GenomeKind) -> &Self::Output {
         &self[kind as usize]
     }
@@ -65,18 +78,20 @@ impl Distribution<Individual> for Standard {
 }
 
 // Learing
-pub fn learning() {
+pub fn learning(settings: &Settings) {
     let mut inds = rand::random::<[Individual; POPULATION]>();
     for gen in 1..=GENERATION_MAX {
-        println!("{gen}世代目");
+        // every individual plays the same block sequence
+        let seed = settings.seed.unwrap_or_else(rand::random);
+        println!("{gen}世代目 (seed: {seed})");
         thread::scope(|s| {
             for (i, ind) in inds.iter_mut().enumerate() {
                 s.spawn(move || {
-                    let mut game = Game::new();
+                    let mut game = settings.new_game_with_seed(seed);
                     // finish remove n line
                     while game.line < LINE_COUNT_MAX {
                         let elite = eval(&game, &ind.geno);
-                        game = elite;
+                        place(&mut game, &elite);
                         // fall elite block
                         if landing(&mut game).is_err() {
                             break;
@@ -119,8 +134,8 @@ fn crossover(inds: &[Individual]) -> [GenoSeq; CROSSOVER_LEN] {
     for i in (0..genos.len() - 1).step_by(2) {
         let mut geno1 = genos[i];
         let mut geno2 = genos[i + 1];
-        let point1 = rng.gen_range(0..4);
-        let point2 = rng.gen_range(point1..4);
+        let point1 = rng.gen_range(0..GENOME_LEN);
+        let point2 = rng.gen_range(point1..GENOME_LEN);
         mem_swap_range(&mut geno1, &mut geno2, point1..=point2);
         genos[i] = geno1;
         genos[i + 1] = geno2;
@@ -142,7 +157,7 @@ fn mutation(inds: &[Individual]) -> [GenoSeq; MUTATION_LEN] {
     let mut rng = rand::thread_rng();
     genos.shuffle(&mut rng);
     for geno in genos.iter_mut().take(MUTATION_LEN) {
-        geno[rng.gen_range(0..4)] = rand::random();
+        geno[rng.gen_range(0..GENOME_LEN)] = rand::random();
     }
     genos[..MUTATION_LEN].try_into().unwrap()
 }
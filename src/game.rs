@@ -1,19 +1,60 @@
 use crate::blocks::{
-    block_kind, block_kind::WALL as W, gen_block_7, BlockColor, BlockShape, Blockkind, BLOCKS,
-    COLOR_TABLE,
+    block_kind, block_kind::WALL as W, kick_table, BlockColor, BlockShape, Blockkind, Rotation,
+    BLOCKS, BLOCK_KINDS, COLOR_TABLE, SHAPES,
 };
+use crate::fumen::Fumen;
+use crate::randomizer::Randomizer;
+use rand::{rngs::StdRng, Rng, SeedableRng};
 use std::collections::VecDeque;
 
 pub const FIELD_WIDTH: usize = 11 + 2 + 2;
 pub const FIELD_HEIGHT: usize = 20 + 1 + 1;
+// columns where blocks can be placed
+pub const PLAYFIELD_WIDTH: usize = FIELD_WIDTH - 4;
 pub const NEXT_LENGTH: usize = 3;
+// number of blocks generated in advance
+pub const QUEUE_LENGTH: usize = 7;
 pub const SCORE_TABLE: [usize; 5] = [
     0,   // 0段消し
-    1,   // 1段消し
-    5,   // 2段消し
-    25,  // 3段消し
-    100, // 4段消し
+    100, // 1段消し
+    300, // 2段消し
+    500, // 3段消し
+    800, // 4段消し
 ];
+pub const TSPIN_SCORE_TABLE: [usize; 4] = [
+    400,  // Tスピン
+    800,  // Tスピンシングル
+    1200, // Tスピンダブル
+    1600, // Tスピントリプル
+];
+pub const TSPIN_MINI_SCORE_TABLE: [usize; 3] = [
+    100, // Tスピンミニ
+    200, // Tスピンミニシングル
+    400, // Tスピンミニダブル
+];
+pub const PERFECT_CLEAR_SCORE_TABLE: [usize; 5] = [
+    0,    // 0段消し
+    800,  // 1段消し
+    1200, // 2段消し
+    1800, // 3段消し
+    2000, // 4段消し
+];
+// perfect clear by a back-to-back tetris
+pub const B2B_PERFECT_CLEAR_SCORE: usize = 3200;
+// score of each combo
+pub const COMBO_SCORE: usize = 50;
+// level goes up every n lines
+pub const LINES_PER_LEVEL: usize = 10;
+// milliseconds per frame, the unit of gravity
+pub const FRAME_MSEC: f64 = 1000.0 / 60.0;
+// gravity of the highest levels, 20 rows per frame
+pub const MAX_GRAVITY: f64 = 20.0;
+// the curve of the gravity ends at the level
+const MAX_GRAVITY_LEVEL: usize = 20;
+// default milliseconds until the block on the ground is fixed
+pub const LOCK_DELAY_MSEC: u64 = 500;
+// number of times moves and rotations can reset the lock delay
+pub const LOCK_RESET_MAX: usize = 15;
 pub type Field = [[BlockColor; FIELD_WIDTH]; FIELD_HEIGHT];
 
 #[derive(Clone, Copy)]
@@ -24,80 +65,246 @@ pub struct Position {
 
 impl Position {
     pub fn init() -> Position {
-        Position { x: 5, y: 0 }
+        Position { x: 5, y: 1 }
+    }
+}
+
+// the block in play
+#[derive(Clone, Copy)]
+pub struct Piece {
+    pub kind: Blockkind,
+    pub rotation: Rotation,
+    pub pos: Position,
+}
+
+impl Piece {
+    pub fn new(kind: Blockkind) -> Piece {
+        Piece {
+            kind,
+            rotation: Rotation::Spawn,
+            pos: Position::init(),
+        }
+    }
+
+    pub fn shape(&self) -> &'static BlockShape {
+        &SHAPES[self.kind as usize][self.rotation as usize]
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum TSpin {
+    None,
+    Mini,
+    Full,
+}
+
+// result of the last landing
+#[derive(Clone, Copy)]
+pub struct Clear {
+    pub line: usize,
+    pub tspin: TSpin,
+    pub combo: Option<usize>,
+    pub b2b: bool,
+    pub perfect: bool,
+}
+
+impl Clear {
+    // tetris and T-spins with lines keep the back-to-back chain
+    pub fn is_difficult(&self) -> bool {
+        self.line == 4 || (self.line > 0 && self.tspin != TSpin::None)
+    }
+
+    pub fn name(&self) -> Option<&'static str> {
+        const LINE_NAMES: [&str; 5] = ["", "SINGLE", "DOUBLE", "TRIPLE", "TETRIS"];
+        match self.tspin {
+            TSpin::None if self.line == 0 => None,
+            TSpin::None => Some(LINE_NAMES[self.line]),
+            TSpin::Mini => {
+                Some(["T-SPIN MINI", "T-SPIN MINI SINGLE", "T-SPIN MINI DOUBLE"][self.line])
+            }
+            TSpin::Full => {
+                Some(["T-SPIN", "T-SPIN SINGLE", "T-SPIN DOUBLE", "T-SPIN TRIPLE"][self.line])
+            }
+        }
     }
 }
 
+// statistics of a game
+#[derive(Clone, Default)]
+pub struct Stats {
+    // number of fixed blocks
+    pub pieces: usize,
+    // fixed blocks of each kind
+    pub kinds: [usize; BLOCK_KINDS.len()],
+    pub tetrises: usize,
+    // T-spins clearing lines, including minis
+    pub tspins: usize,
+}
+
 #[derive(Clone)]
 pub struct Game {
     pub field: Field,
-    pub pos: Position,
-    pub block: BlockShape,
-    pub hold: Option<BlockShape>,
+    pub piece: Piece,
+    pub hold: Option<Blockkind>,
     pub holded: bool,
-    pub next: VecDeque<BlockShape>,
-    pub next_buf: VecDeque<BlockShape>,
+    pub next: VecDeque<Blockkind>,
+    // seed of the block sequence
+    pub seed: u64,
+    pub rng: StdRng,
+    // holes of the garbage, separated from the blocks
+    pub garbage_rng: StdRng,
+    pub randomizer: Box<dyn Randomizer>,
+    // kick index of the last rotation, if the last move was a rotation
+    pub last_kick: Option<usize>,
+    pub last_clear: Option<Clear>,
+    // number of combos, None if the last block did not erase lines
+    pub combo: Option<usize>,
+    // number of back-to-backs, None if the chain is broken
+    pub b2b: Option<usize>,
     pub score: usize,
     pub line: usize,
+    pub level: usize,
+    pub stats: Stats,
+    // milliseconds since the game started
+    pub time: u64,
+    // milliseconds since the last fall
+    pub gravity_timer: f64,
+    pub lock_delay: u64,
+    // milliseconds the block has been on the ground
+    pub lock_timer: u64,
+    pub lock_resets: usize,
+    // lowest row the block has reached
+    pub lowest_y: usize,
 }
 
 impl Game {
-    pub fn new() -> Game {
-        let mut game = Game {
-            field: [
-                [0, W, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, W, 0],
-                [0, W, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, W, 0],
-                [0, W, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, W, 0],
-                [0, W, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, W, 0],
-                [0, W, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, W, 0],
-                [0, W, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, W, 0],
-                [0, W, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, W, 0],
-                [0, W, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, W, 0],
-                [0, W, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, W, 0],
-                [0, W, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, W, 0],
-                [0, W, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, W, 0],
-                [0, W, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, W, 0],
-                [0, W, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, W, 0],
-                [0, W, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, W, 0],
-                [0, W, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, W, 0],
-                [0, W, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, W, 0],
-                [0, W, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, W, 0],
-                [0, W, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, W, 0],
-                [0, W, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, W, 0],
-                [0, W, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, W, 0],
-                [0, W, W, W, W, W, W, W, W, W, W, W, W, W, 0],
-                [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
-            ],
-            pos: Position::init(),
-            block: BLOCKS[rand::random::<Blockkind>() as usize],
+    pub fn new(seed: u64, mut randomizer: Box<dyn Randomizer>) -> Game {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let piece = Piece::new(randomizer.next_block(&mut rng));
+        let next = (0..QUEUE_LENGTH)
+            .map(|_| randomizer.next_block(&mut rng))
+            .collect();
+        Game {
+            field: empty_field(),
+            piece,
             hold: None,
             holded: false,
-            next: gen_block_7().into(),
-            next_buf: gen_block_7().into(),
+            next,
+            seed,
+            rng,
+            garbage_rng: StdRng::seed_from_u64(!seed),
+            randomizer,
+            last_kick: None,
+            last_clear: None,
+            combo: None,
+            b2b: None,
             score: 0,
             line: 0,
-        };
+            level: 1,
+            stats: Stats::default(),
+            time: 0,
+            gravity_timer: 0.0,
+            lock_delay: LOCK_DELAY_MSEC,
+            lock_timer: 0,
+            lock_resets: 0,
+            lowest_y: piece.pos.y,
+        }
+    }
+
+    // start from the field and the block of the fumen
+    pub fn load_fumen(&mut self, fumen: &Fumen) {
+        self.field = fumen.field;
+        if let Some(piece) = fumen.piece {
+            if !is_collision(&self.field, &piece.pos, piece.shape()) {
+                self.piece = piece;
+                self.lowest_y = piece.pos.y;
+            }
+        }
+    }
+
+    // fill the bottom rows with garbage, each with a random hole
+    pub fn fill_garbage(&mut self, rows: usize) {
+        for y in FIELD_HEIGHT - 2 - rows..FIELD_HEIGHT - 2 {
+            let hole = self.garbage_rng.gen_range(0..PLAYFIELD_WIDTH);
+            self.field[y] = garbage_row(hole);
+        }
+    }
+}
 
-        spawn_block(&mut game).ok();
-        game
+// row of garbage with a hole at the column from the left of the playfield
+pub fn garbage_row(hole: usize) -> [BlockColor; FIELD_WIDTH] {
+    let mut row = [block_kind::NONE; FIELD_WIDTH];
+    row[1] = W;
+    row[FIELD_WIDTH - 2] = W;
+    for (x, cell) in row[2..FIELD_WIDTH - 2].iter_mut().enumerate() {
+        if x != hole {
+            *cell = block_kind::GARBAGE;
+        }
     }
+    row
 }
 
-#[allow(clippy::needless_range_loop)]
-pub fn draw(
+// the walls and the floor around the empty playfield
+pub fn empty_field() -> Field {
+    [
+        [0, W, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, W, 0],
+        [0, W, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, W, 0],
+        [0, W, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, W, 0],
+        [0, W, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, W, 0],
+        [0, W, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, W, 0],
+        [0, W, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, W, 0],
+        [0, W, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, W, 0],
+        [0, W, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, W, 0],
+        [0, W, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, W, 0],
+        [0, W, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, W, 0],
+        [0, W, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, W, 0],
+        [0, W, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, W, 0],
+        [0, W, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, W, 0],
+        [0, W, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, W, 0],
+        [0, W, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, W, 0],
+        [0, W, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, W, 0],
+        [0, W, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, W, 0],
+        [0, W, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, W, 0],
+        [0, W, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, W, 0],
+        [0, W, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, W, 0],
+        [0, W, W, W, W, W, W, W, W, W, W, W, W, W, 0],
+        [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+    ]
+}
+
+// number of rows with garbage left
+pub fn garbage_rows(field: &Field) -> usize {
+    field[..FIELD_HEIGHT - 2]
+        .iter()
+        .filter(|row| row.contains(&block_kind::GARBAGE))
+        .count()
+}
+
+pub fn draw(game: &Game) {
+    draw_at(game, 0);
+}
+
+// draw the game with the left end at the column
+pub fn draw_at(
     Game {
         field,
-        pos,
-        block,
+        piece,
         hold,
         holded: _,
         next,
-        next_buf: _,
-        score, ..
+        score,
+        line,
+        level,
+        last_clear,
+        seed,
+        ..
     }: &Game,
+    left: usize,
 ) {
     let mut field_buf = *field;
 
+    let pos = &piece.pos;
+    let block = piece.shape();
     let ghost_pos = ghost_pos(field, pos, block);
     for y in 0..4 {
         for x in 0..4 {
@@ -114,11 +321,12 @@ pub fn draw(
             }
         }
     }
-    println!("\x1b[2;28HHOLD");
+    println!("\x1b[2;{}HHOLD", left + 28);
 
     if let Some(hold) = hold {
+        let hold = &BLOCKS[*hold as usize];
         for y in 0..4 {
-            print!("\x1b[{};28H", y + 3);
+            print!("\x1b[{};{}H", y + 3, left + 28);
             for x in 0..4 {
                 print!("{}", COLOR_TABLE[hold[y][x]]);
             }
@@ -126,10 +334,11 @@ pub fn draw(
         }
     }
 
-    println!("\x1b[8;28HNEXT");
+    println!("\x1b[8;{}HNEXT", left + 28);
     for (i, next) in next.iter().take(NEXT_LENGTH).enumerate() {
+        let next = &BLOCKS[*next as usize];
         for y in 0..4 {
-            print!("\x1b[{};28H", i * 4 + y + 9);
+            print!("\x1b[{};{}H", i * 4 + y + 9, left + 28);
             for x in 0..4 {
                 print!("{}", COLOR_TABLE[next[y][x]]);
             }
@@ -137,11 +346,49 @@ pub fn draw(
         }
     }
 
-    println!("\x1b[22;28H{}", score);
-
-    println!("\x1b[H");
+    print!("\x1b[0m");
+    println!("\x1b[2;{}HSCORE", left + 38);
+    println!("\x1b[3;{}H{}", left + 38, score);
+    println!("\x1b[5;{}HLINES", left + 38);
+    println!("\x1b[6;{}H{}", left + 38, line);
+    println!("\x1b[8;{}HLEVEL", left + 38);
+    println!("\x1b[9;{}H{}", left + 38, level);
+    println!("\x1b[16;{}HSEED", left + 38);
+    println!("\x1b[17;{}H{}", left + 38, seed);
+
+    let clear = last_clear.filter(|clear| clear.line > 0 || clear.tspin != TSpin::None);
+    // pad the texts instead of clearing the line for the game drawn on the right
+    println!(
+        "\x1b[11;{}H{:<18}",
+        left + 38,
+        clear.and_then(|clear| clear.name()).unwrap_or_default()
+    );
+    println!(
+        "\x1b[12;{}H{:<18}",
+        left + 38,
+        if clear.is_some_and(|clear| clear.b2b) {
+            "BACK-TO-BACK"
+        } else {
+            ""
+        }
+    );
+    let combo = match clear.and_then(|clear| clear.combo) {
+        Some(combo @ 1..) => format!("{} COMBO", combo),
+        _ => String::new(),
+    };
+    println!("\x1b[13;{}H{:<18}", left + 38, combo);
+    println!(
+        "\x1b[14;{}H{:<18}",
+        left + 38,
+        if clear.is_some_and(|clear| clear.perfect) {
+            "PERFECT CLEAR"
+        } else {
+            ""
+        }
+    );
 
     for y in 0..FIELD_HEIGHT - 1 {
+        print!("\x1b[{};{}H", y + 2, left + 1);
         for x in 1..FIELD_WIDTH - 1 {
             print!("{}", COLOR_TABLE[field_buf[y][x]]);
         }
@@ -164,11 +411,9 @@ pub fn is_collision(field: &Field, pos: &Position, block: &BlockShape) -> bool {
     false
 }
 
-pub fn fix_block(
-    Game {
-        field, pos, block, ..
-    }: &mut Game,
-) {
+pub fn fix_block(Game { field, piece, .. }: &mut Game) {
+    let pos = &piece.pos;
+    let block = piece.shape();
     for y in 0..4 {
         for x in 0..4 {
             if block[y][x] != block_kind::NONE {
@@ -178,6 +423,7 @@ pub fn fix_block(
     }
 }
 
+#[allow(clippy::needless_range_loop)]
 pub fn erase_line(field: &mut Field) -> usize {
     let mut count = 0;
     for y in 1..FIELD_HEIGHT - 2 {
@@ -198,25 +444,118 @@ pub fn erase_line(field: &mut Field) -> usize {
     count
 }
 
+// push up the field and insert the rows of garbage with a hole at the column
+// the block is pushed up together, returns Err if anything goes over the top
+pub fn insert_garbage(game: &mut Game, rows: usize, hole: usize) -> Result<(), ()> {
+    if rows > FIELD_HEIGHT - 2 {
+        return Err(());
+    }
+    if game.field[..rows].iter().any(|row| {
+        row[2..FIELD_WIDTH - 2]
+            .iter()
+            .any(|&c| c != block_kind::NONE)
+    }) {
+        return Err(());
+    }
+    let bottom = FIELD_HEIGHT - 2;
+    game.field.copy_within(rows..bottom, 0);
+    for row in &mut game.field[bottom - rows..bottom] {
+        *row = garbage_row(hole);
+    }
+
+    while is_collision(&game.field, &game.piece.pos, game.piece.shape()) {
+        if game.piece.pos.y == 0 {
+            return Err(());
+        }
+        game.piece.pos.y -= 1;
+    }
+    game.lowest_y = game.lowest_y.saturating_sub(rows);
+    Ok(())
+}
+
 pub fn move_block(game: &mut Game, new_pos: Position) {
-    if !is_collision(&game.field, &new_pos, &game.block) {
-        game.pos = new_pos;
+    if !is_collision(&game.field, &new_pos, game.piece.shape()) {
+        game.piece.pos = new_pos;
+        game.last_kick = None;
+        reset_lock_delay(game);
     }
 }
 
-pub fn spawn_block(game: &mut Game) -> Result<(), ()> {
-    game.pos = Position::init();
+// put a new block in play
+fn set_piece(game: &mut Game, kind: Blockkind) {
+    game.piece = Piece::new(kind);
+    game.last_kick = None;
+    game.gravity_timer = 0.0;
+    game.lock_timer = 0;
+    game.lock_resets = 0;
+    game.lowest_y = game.piece.pos.y;
+}
 
-    game.block = game.next.pop_front().unwrap();
+// the block can not fall any more
+pub fn is_grounded(game: &Game) -> bool {
+    let pos = Position {
+        x: game.piece.pos.x,
+        y: game.piece.pos.y + 1,
+    };
+    is_collision(&game.field, &pos, game.piece.shape())
+}
 
-    if let Some(next) = game.next_buf.pop_front() {
-        game.next.push_back(next);
-    } else {
-        game.next_buf = gen_block_7().into();
-        game.next.push_back(game.next_buf.pop_front().unwrap());
+// reset the lock delay after the block moved
+fn reset_lock_delay(game: &mut Game) {
+    if game.piece.pos.y > game.lowest_y {
+        // reaching a new row gives all the resets back
+        game.lowest_y = game.piece.pos.y;
+        game.lock_timer = 0;
+        game.lock_resets = 0;
+    } else if game.lock_timer > 0 && game.lock_resets < LOCK_RESET_MAX {
+        game.lock_timer = 0;
+        game.lock_resets += 1;
     }
+}
+
+// advance the game by 1 millisecond of gravity and lock delay
+// returns whether the block moved or landed
+pub fn update(game: &mut Game) -> Result<bool, ()> {
+    game.time += 1;
+    let mut moved = false;
+    if !is_grounded(game) {
+        game.gravity_timer += 1.0;
+        if is_max_gravity(game.level) {
+            // 20G
+            hard_drop(game);
+            moved = true;
+        }
+        let gravity = gravity_msec(game.level);
+        while game.gravity_timer >= gravity && !is_grounded(game) {
+            game.gravity_timer -= gravity;
+            game.piece.pos.y += 1;
+            moved = true;
+        }
+        if moved {
+            game.last_kick = None;
+            reset_lock_delay(game);
+        }
+    }
+
+    if is_grounded(game) {
+        game.gravity_timer = 0.0;
+        game.lock_timer += 1;
+        if game.lock_timer >= game.lock_delay {
+            landing(game)?;
+            moved = true;
+        }
+    }
+    Ok(moved)
+}
 
-    if is_collision(&game.field, &game.pos, &game.block) {
+pub fn spawn_block(game: &mut Game) -> Result<(), ()> {
+    let kind = game.next.pop_front().unwrap();
+    set_piece(game, kind);
+
+    let next = game.randomizer.next_block(&mut game.rng);
+    game.next.push_back(next);
+
+    if is_collision(&game.field, &game.piece.pos, game.piece.shape()) {
         Err(())
     } else {
         Ok(())
@@ -233,60 +572,106 @@ pub fn quit() {
     println!("\x1b[?25h");
 }
 
-#[allow(clippy::needless_range_loop)]
 pub fn rotate_right(game: &mut Game) {
-    let mut new_shape: BlockShape = Default::default();
-    for y in 0..4 {
-        for x in 0..4 {
-            new_shape[y][x] = game.block[4 - 1 - x][y];
-        }
-    }
-    if !is_collision(&game.field, &game.pos, &new_shape) {
-        game.block = new_shape;
-    } else if let Ok(new_pos) = super_rotation(&game.field, &game.pos, &new_shape) {
-        game.pos = new_pos;
-        game.block = new_shape;
-    }
+    rotate(game, 0);
 }
 
-#[allow(clippy::needless_range_loop)]
 pub fn rotate_left(game: &mut Game) {
-    let mut new_shape: BlockShape = Default::default();
-    for y in 0..4 {
-        for x in 0..4 {
-            new_shape[4 - 1 - x][y] = game.block[y][x];
-        }
-    }
-    if !is_collision(&game.field, &game.pos, &new_shape) {
-        game.block = new_shape;
-    } else if let Ok(new_pos) = super_rotation(&game.field, &game.pos, &new_shape) {
-        game.pos = new_pos;
-        game.block = new_shape;
+    rotate(game, 1);
+}
+
+// direction 0: clockwise, 1: counterclockwise
+fn rotate(game: &mut Game, direction: usize) {
+    if let Some((piece, kick)) = rotated(&game.field, &game.piece, direction) {
+        game.piece = piece;
+        game.last_kick = Some(kick);
+        reset_lock_delay(game);
     }
 }
 
+// the block rotated with the wall kicks and the index of the kick used
+pub fn rotated(field: &Field, piece: &Piece, direction: usize) -> Option<(Piece, usize)> {
+    let mut rotated = *piece;
+    rotated.rotation = if direction == 0 {
+        piece.rotation.cw()
+    } else {
+        piece.rotation.ccw()
+    };
+    let kicks = &kick_table(piece.kind)[piece.rotation as usize][direction];
+    let (pos, kick) = super_rotation(field, &piece.pos, rotated.shape(), kicks).ok()?;
+    rotated.pos = pos;
+    Some((rotated, kick))
+}
+
 pub fn hard_drop(game: &mut Game) {
-    while {
-        let new_pos = Position {
-            x: game.pos.x,
-            y: game.pos.y + 1,
-        };
-        !is_collision(&game.field, &new_pos, &game.block)
-    } {
-        game.pos.y += 1;
+    let new_pos = ghost_pos(&game.field, &game.piece.pos, game.piece.shape());
+    // the block on the ground keeps its last rotation for T-spins
+    if new_pos.y != game.piece.pos.y {
+        move_block(game, new_pos);
     }
-    let new_pos = game.pos;
-    move_block(game, new_pos);
 }
 
 pub fn landing(game: &mut Game) -> Result<(), ()> {
+    let tspin = tspin(game);
+
     fix_block(game);
+    game.stats.pieces += 1;
+    game.stats.kinds[game.piece.kind as usize] += 1;
+
+    let erased = erase_line(&mut game.field);
+    // full rows loaded from a fumen may clear more lines than the tables have
+    let line = erased.min(match tspin {
+        TSpin::None => SCORE_TABLE.len() - 1,
+        TSpin::Mini => TSPIN_MINI_SCORE_TABLE.len() - 1,
+        TSpin::Full => TSPIN_SCORE_TABLE.len() - 1,
+    });
+    if line == 4 {
+        game.stats.tetrises += 1;
+    }
+    if line > 0 && tspin != TSpin::None {
+        game.stats.tspins += 1;
+    }
 
-    let line = erase_line(&mut game.field);
+    game.combo = if line > 0 {
+        Some(game.combo.map_or(0, |combo| combo + 1))
+    } else {
+        None
+    };
+    let mut clear = Clear {
+        line,
+        tspin,
+        combo: game.combo,
+        b2b: false,
+        perfect: line > 0 && is_perfect_clear(&game.field),
+    };
+    if clear.is_difficult() {
+        clear.b2b = game.b2b.is_some();
+        game.b2b = Some(game.b2b.map_or(0, |b2b| b2b + 1));
+    } else if line > 0 {
+        game.b2b = None;
+    }
 
-    game.score += SCORE_TABLE[line];
+    let mut score = match tspin {
+        TSpin::None => SCORE_TABLE[line],
+        TSpin::Mini => TSPIN_MINI_SCORE_TABLE[line],
+        TSpin::Full => TSPIN_SCORE_TABLE[line],
+    };
+    if clear.b2b {
+        score = score * 3 / 2;
+    }
+    score += COMBO_SCORE * clear.combo.unwrap_or(0);
+    if clear.perfect {
+        score += if clear.b2b && line == 4 {
+            B2B_PERFECT_CLEAR_SCORE
+        } else {
+            PERFECT_CLEAR_SCORE_TABLE[line]
+        };
+    }
+    game.score += score * game.level;
+    game.last_clear = Some(clear);
 
-    game.line += line;
+    game.line += erased;
+    game.level = game.level.max(game.line / LINES_PER_LEVEL + 1);
 
     spawn_block(game)?;
 
@@ -294,6 +679,63 @@ pub fn landing(game: &mut Game) -> Result<(), ()> {
     Ok(())
 }
 
+// milliseconds for the block to fall one row at the level (guideline curve)
+// the base of the curve goes negative over the level 115
+pub fn gravity_msec(level: usize) -> f64 {
+    let level = level.min(MAX_GRAVITY_LEVEL) as f64;
+    let msec = (0.8 - (level - 1.0) * 0.007).powf(level - 1.0) * 1000.0;
+    msec.max(FRAME_MSEC / MAX_GRAVITY)
+}
+
+// the block falls to the bottom at once
+pub fn is_max_gravity(level: usize) -> bool {
+    gravity_msec(level) <= FRAME_MSEC / MAX_GRAVITY
+}
+
+// no blocks left in the field
+pub fn is_perfect_clear(field: &Field) -> bool {
+    field[..FIELD_HEIGHT - 2].iter().all(|row| {
+        row[2..FIELD_WIDTH - 2]
+            .iter()
+            .all(|&c| c == block_kind::NONE)
+    })
+}
+
+// T-spin check by the 3-corner rule
+pub fn tspin(game: &Game) -> TSpin {
+    let piece = &game.piece;
+    let Some(kick) = game.last_kick else {
+        return TSpin::None;
+    };
+    if piece.kind != Blockkind::T {
+        return TSpin::None;
+    }
+
+    let corners = tspin_corners(&game.field, piece);
+    // corners on the side T block points to
+    let front = match piece.rotation {
+        Rotation::Spawn => [0, 1],
+        Rotation::Right => [1, 3],
+        Rotation::Reverse => [2, 3],
+        Rotation::Left => [0, 2],
+    };
+
+    if corners.iter().filter(|&&c| c).count() < 3 {
+        TSpin::None
+    } else if front.iter().all(|&i| corners[i]) || kick == 4 {
+        // the last kick of the table always makes a full T-spin
+        TSpin::Full
+    } else {
+        TSpin::Mini
+    }
+}
+
+// corners around the center of T block (top left, top right, bottom left, bottom right)
+pub fn tspin_corners(field: &Field, piece: &Piece) -> [bool; 4] {
+    [(0, 0), (2, 0), (0, 2), (2, 2)]
+        .map(|(x, y)| field[piece.pos.y + y][piece.pos.x + x] != block_kind::NONE)
+}
+
 fn ghost_pos(field: &Field, pos: &Position, block: &BlockShape) -> Position {
     let mut ghost_pos = *pos;
     while {
@@ -308,29 +750,21 @@ fn ghost_pos(field: &Field, pos: &Position, block: &BlockShape) -> Position {
     ghost_pos
 }
 
-fn super_rotation(field: &Field, pos: &Position, block: &BlockShape) -> Result<Position, ()> {
-    let diff_pos = [
-        Position {
-            x: pos.x,
-            y: pos.y.checked_sub(1).unwrap_or(pos.y),
-        },
-        Position {
-            x: pos.x + 1,
-            y: pos.y,
-        },
-        Position {
-            x: pos.x,
-            y: pos.y + 1,
-        },
-        Position {
-            x: pos.x.checked_sub(1).unwrap_or(pos.x),
-            y: pos.y,
-        },
-    ];
-
-    for pos in diff_pos {
+fn super_rotation(
+    field: &Field,
+    pos: &Position,
+    block: &BlockShape,
+    kicks: &[(isize, isize); 5],
+) -> Result<(Position, usize), ()> {
+    for (i, (dx, dy)) in kicks.iter().enumerate() {
+        // the kick table's y axis points up
+        let (Some(x), Some(y)) = (pos.x.checked_add_signed(*dx), pos.y.checked_add_signed(-dy))
+        else {
+            continue;
+        };
+        let pos = Position { x, y };
         if !is_collision(field, &pos, block) {
-            return Ok(pos);
+            return Ok((pos, i));
         }
     }
 
@@ -341,14 +775,209 @@ pub fn hold(game: &mut Game) {
     if game.holded {
         return;
     }
-    if let Some(mut hold) = game.hold {
-        std::mem::swap(&mut hold, &mut game.block);
-        game.hold = Some(hold);
-        game.pos = Position::init();
+    if let Some(hold) = game.hold {
+        game.hold = Some(game.piece.kind);
+        set_piece(game, hold);
     } else {
-        game.hold = Some(game.block);
+        game.hold = Some(game.piece.kind);
         spawn_block(game).ok();
     }
 
     game.holded = true;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blocks::BLOCK_KINDS;
+    use crate::randomizer::Bag7;
+
+    #[test]
+    fn test_seed() {
+        let sequence = |seed| {
+            let mut game = Game::new(seed, Box::<Bag7>::default());
+            (0..20)
+                .map(|_| {
+                    spawn_block(&mut game).ok();
+                    game.piece.kind as usize
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(sequence(1), sequence(1));
+        assert_ne!(sequence(1), sequence(2));
+    }
+
+    #[test]
+    fn test_rotate_cycle() {
+        for kind in BLOCK_KINDS {
+            let mut game = Game::new(0, Box::<Bag7>::default());
+            game.piece = Piece::new(kind);
+            for _ in 0..4 {
+                rotate_right(&mut game);
+            }
+            assert!(game.piece.rotation == Rotation::Spawn);
+            assert_eq!(game.piece.shape(), &BLOCKS[kind as usize]);
+            rotate_left(&mut game);
+            assert!(game.piece.rotation == Rotation::Left);
+        }
+    }
+
+    #[test]
+    fn test_wall_kick() {
+        let mut game = Game::new(0, Box::<Bag7>::default());
+        game.piece = Piece::new(Blockkind::I);
+        rotate_right(&mut game);
+        assert!(game.piece.rotation == Rotation::Right);
+        // put the vertical I block against the left wall
+        game.piece.pos.x = 0;
+        assert!(!is_collision(
+            &game.field,
+            &game.piece.pos,
+            game.piece.shape()
+        ));
+        // R -> 0 is kicked (+2, 0) away from the wall
+        rotate_left(&mut game);
+        assert!(game.piece.rotation == Rotation::Spawn);
+        assert_eq!(game.piece.pos.x, 2);
+        assert_eq!(game.piece.pos.y, Position::init().y);
+    }
+
+    #[test]
+    fn test_tspin_double() {
+        let mut game = Game::new(0, Box::<Bag7>::default());
+        for x in 2..FIELD_WIDTH - 2 {
+            if x != 5 {
+                game.field[19][x] = block_kind::I;
+            }
+            if !(4..=6).contains(&x) {
+                game.field[18][x] = block_kind::I;
+            }
+        }
+        // overhang of the T slot
+        game.field[17][4] = block_kind::I;
+
+        game.piece = Piece::new(Blockkind::T);
+        game.piece.pos = Position { x: 4, y: 17 };
+        rotate_right(&mut game);
+        rotate_right(&mut game);
+        assert!(game.piece.rotation == Rotation::Reverse);
+        assert!(tspin(&game) == TSpin::Full);
+
+        // moving after the rotation is not a T-spin
+        let mut moved = game.clone();
+        move_block(&mut moved, game.piece.pos);
+        assert!(tspin(&moved) == TSpin::None);
+
+        // the hard drop on the ground keeps the rotation
+        hard_drop(&mut game);
+        assert!(tspin(&game) == TSpin::Full);
+        // after a tetris
+        game.b2b = Some(0);
+        landing(&mut game).ok();
+        assert_eq!(game.line, 2);
+        assert_eq!(game.score, TSPIN_SCORE_TABLE[2] * 3 / 2);
+        assert_eq!(game.last_clear.unwrap().name(), Some("T-SPIN DOUBLE"));
+        assert!(game.last_clear.unwrap().b2b);
+        assert_eq!(game.b2b, Some(1));
+        assert_eq!(game.stats.tspins, 1);
+        assert_eq!(game.stats.kinds[Blockkind::T as usize], 1);
+    }
+
+    #[test]
+    fn test_gravity_curve() {
+        assert_eq!(gravity_msec(1), 1000.0);
+        for level in 1..30 {
+            assert!(gravity_msec(level + 1) <= gravity_msec(level));
+        }
+        assert!(!is_max_gravity(15));
+        assert!(is_max_gravity(20));
+        // the curve stops before its base goes negative
+        for level in [115, 116, 301, 1000, usize::MAX] {
+            assert_eq!(gravity_msec(level), gravity_msec(20));
+        }
+    }
+
+    #[test]
+    fn test_lock_delay() {
+        let mut game = Game::new(0, Box::<Bag7>::default());
+        game.piece = Piece::new(Blockkind::O);
+        hard_drop(&mut game);
+        for _ in 1..game.lock_delay {
+            update(&mut game).ok();
+        }
+        assert_eq!(game.line, 0);
+        assert!(game.field[19].iter().all(|&c| c != block_kind::O));
+
+        // moving on the ground resets the lock delay up to LOCK_RESET_MAX times
+        for i in 0..LOCK_RESET_MAX + 1 {
+            let dx = if i % 2 == 0 { 1 } else { -1 };
+            let new_pos = Position {
+                x: game.piece.pos.x.checked_add_signed(dx).unwrap(),
+                y: game.piece.pos.y,
+            };
+            move_block(&mut game, new_pos);
+            update(&mut game).ok();
+        }
+        assert_eq!(game.lock_resets, LOCK_RESET_MAX);
+        let lock_timer = game.lock_timer;
+        assert!(lock_timer > 0);
+        let new_pos = Position {
+            x: game.piece.pos.x + 1,
+            y: game.piece.pos.y,
+        };
+        move_block(&mut game, new_pos);
+        assert_eq!(game.lock_timer, lock_timer);
+    }
+
+    #[test]
+    fn test_perfect_clear() {
+        let mut game = Game::new(0, Box::<Bag7>::default());
+        for x in 6..FIELD_WIDTH - 2 {
+            game.field[19][x] = block_kind::I;
+        }
+        game.piece = Piece::new(Blockkind::I);
+        game.piece.pos = Position { x: 2, y: 18 };
+        game.combo = Some(1);
+        landing(&mut game).ok();
+        let clear = game.last_clear.unwrap();
+        assert!(clear.perfect);
+        assert_eq!(clear.combo, Some(2));
+        assert_eq!(
+            game.score,
+            SCORE_TABLE[1] + COMBO_SCORE * 2 + PERFECT_CLEAR_SCORE_TABLE[1]
+        );
+    }
+
+    #[test]
+    fn test_garbage() {
+        let mut game = Game::new(0, Box::<Bag7>::default());
+        game.fill_garbage(5);
+        assert_eq!(garbage_rows(&game.field), 5);
+        for row in &game.field[FIELD_HEIGHT - 7..FIELD_HEIGHT - 2] {
+            let holes = row[2..FIELD_WIDTH - 2]
+                .iter()
+                .filter(|&&c| c == block_kind::NONE)
+                .count();
+            assert_eq!(holes, 1);
+        }
+        assert!(game.field[FIELD_HEIGHT - 8]
+            .iter()
+            .all(|&c| c != block_kind::GARBAGE));
+    }
+
+    #[test]
+    fn test_insert_garbage() {
+        let mut game = Game::new(0, Box::<Bag7>::default());
+        game.piece = Piece::new(Blockkind::O);
+        game.piece.pos.y = 18;
+        game.field[19][2] = block_kind::I;
+        assert!(insert_garbage(&mut game, 2, 3).is_ok());
+        assert_eq!(game.field[17][2], block_kind::I);
+        assert_eq!(game.field[18], garbage_row(3));
+        assert_eq!(game.field[19], garbage_row(3));
+        assert_eq!(game.piece.pos.y, 16);
+
+        game.field[1][5] = block_kind::I;
+        assert!(insert_garbage(&mut game, 2, 3).is_err());
+    }
+}
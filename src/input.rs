@@ -0,0 +1,475 @@
+use crate::game::*;
+use getch_rs::{Getch, Key};
+use std::io::{self, Read, Write};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+// flags of the kitty keyboard protocol to get key release events
+// 1: disambiguate escape codes, 2: report event types, 8: report all keys as escape codes
+const KEYBOARD_FLAGS: u8 = 1 | 2 | 8;
+
+pub const DAS_MSEC: u64 = 167;
+pub const ARR_MSEC: u64 = 33;
+pub const SOFT_DROP_FACTOR: u64 = 20;
+
+// wait for the replies of the terminal to the queries
+const QUERY_TIMEOUT_MSEC: u64 = 200;
+// time to read the warning before the game starts
+const WARNING_MSEC: u64 = 2000;
+
+#[derive(Clone, PartialEq, Eq)]
+pub enum KeyEvent {
+    Press(Key),
+    Release(Key),
+}
+
+// keys of the terminal with press and release events
+//
+// terminals supporting the kitty keyboard protocol report key releases,
+// other terminals send each key (and its key repeat) as a press and an immediate release
+pub struct Input {
+    events: mpsc::Receiver<KeyEvent>,
+    // the terminal reports key releases
+    release: bool,
+    // keep the terminal raw until the input is dropped
+    _getch: Getch,
+}
+
+impl Input {
+    pub fn new() -> Input {
+        let getch = Getch::new();
+        // enable the protocol and ask whether the terminal supports it,
+        // the device attributes are asked last as every terminal replies to them
+        print!("\x1b[>{}u\x1b[?u\x1b[c", KEYBOARD_FLAGS);
+        io::stdout().flush().ok();
+
+        let (tx, rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel();
+        thread::spawn(move || read_keys(tx, release_tx));
+        let release = release_rx
+            .recv_timeout(Duration::from_millis(QUERY_TIMEOUT_MSEC))
+            .unwrap_or(false);
+        Input {
+            events: rx,
+            release,
+            _getch: getch,
+        }
+    }
+
+    // the held keys repeat by DAS and ARR only with the key releases,
+    // otherwise they repeat by the key repeat of the terminal
+    pub fn warn_no_release(&self) {
+        if self.release {
+            return;
+        }
+        println!("the terminal does not report key releases (kitty keyboard protocol)");
+        println!("--das, --arr and --sdf take no effect");
+        thread::sleep(Duration::from_millis(WARNING_MSEC));
+    }
+
+    pub fn try_iter(&self) -> mpsc::TryIter<'_, KeyEvent> {
+        self.events.try_iter()
+    }
+
+    pub fn recv(&self) -> Option<KeyEvent> {
+        self.events.recv().ok()
+    }
+}
+
+impl Drop for Input {
+    fn drop(&mut self) {
+        print!("\x1b[<u");
+        io::stdout().flush().ok();
+    }
+}
+
+// whether the terminal reports key releases is sent to release_tx
+fn read_keys(tx: mpsc::Sender<KeyEvent>, release_tx: mpsc::Sender<bool>) {
+    let mut bytes = io::stdin().lock().bytes().map_while(Result::ok);
+    // the terminal reports key releases
+    let mut kitty = false;
+    while let Some(byte) = bytes.next() {
+        let (key, pressed) = match byte {
+            b'\x1b' => {
+                if bytes.next() != Some(b'[') {
+                    continue;
+                }
+                // parameters and the final byte of CSI sequence
+                let mut params = vec![];
+                let Some(last) = bytes.find(|&c| {
+                    let is_last = (0x40..=0x7e).contains(&c);
+                    if !is_last {
+                        params.push(c);
+                    }
+                    is_last
+                }) else {
+                    return;
+                };
+                if params.first() == Some(&b'?') {
+                    match last {
+                        // reply to the query of the keyboard protocol
+                        b'u' => {
+                            kitty = true;
+                            release_tx.send(true).ok();
+                        }
+                        // reply to the device attributes without the protocol
+                        b'c' if !kitty => {
+                            release_tx.send(false).ok();
+                        }
+                        _ => (),
+                    }
+                    continue;
+                }
+                match parse_csi(&params, last) {
+                    Some((key, Some(pressed))) => (key, Some(pressed)),
+                    // arrow keys without modifiers are pressed in the kitty protocol
+                    Some((key, None)) if kitty => (key, Some(true)),
+                    Some((key, None)) => (key, None),
+                    None => continue,
+                }
+            }
+            b'\r' | b'\n' => (Key::Char('\r'), None),
+            b'\x7f' => (Key::Delete, None),
+            c @ b'\x01'..=b'\x1a' => (Key::Ctrl((c - 0x1 + b'a') as char), None),
+            c if c.is_ascii() => (Key::Char(c as char), None),
+            _ => continue,
+        };
+
+        let events = match pressed {
+            Some(true) => vec![KeyEvent::Press(key)],
+            Some(false) => vec![KeyEvent::Release(key)],
+            // no release events from the terminal
+            None => vec![KeyEvent::Press(key.clone()), KeyEvent::Release(key)],
+        };
+        for event in events {
+            if tx.send(event).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+// parse CSI sequence of a key
+// returns the key and whether it is pressed or released, None for legacy sequences
+fn parse_csi(params: &[u8], last: u8) -> Option<(Key, Option<bool>)> {
+    let params = std::str::from_utf8(params).ok()?;
+    let mut params = params.split(';');
+    // key code and its alternates
+    let code = params.next().unwrap_or_default().split(':').next()?;
+    // modifiers and event type
+    let (modifiers, event) = match params.next() {
+        Some(param) => {
+            let mut param = param.split(':');
+            let modifiers = param.next()?.parse::<u32>().ok()?;
+            let event = match param.next() {
+                Some(event) => Some(event.parse::<u32>().ok()?),
+                None => None,
+            };
+            (modifiers, event)
+        }
+        None => (1, None),
+    };
+
+    let key = match last {
+        b'A' => Key::Up,
+        b'B' => Key::Down,
+        b'C' => Key::Right,
+        b'D' => Key::Left,
+        b'u' => match code.parse::<u32>().ok()? {
+            9 => Key::Char('\t'),
+            13 => Key::Char('\r'),
+            27 => Key::Esc,
+            127 => Key::Delete,
+            // control key
+            c if modifiers.saturating_sub(1) & 4 != 0 => Key::Ctrl(char::from_u32(c)?),
+            c => Key::Char(char::from_u32(c)?),
+        },
+        _ => return None,
+    };
+
+    match event {
+        // key repeats of the terminal are ignored
+        Some(2) => None,
+        Some(3) => Some((key, Some(false))),
+        Some(_) => Some((key, Some(true))),
+        None if last == b'u' => Some((key, Some(true))),
+        None => Some((key, None)),
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Left,
+    Right,
+    SoftDrop,
+    HardDrop,
+    RotateLeft,
+    RotateRight,
+    Hold,
+    Quit,
+}
+
+// key bindings of a player
+pub struct Keymap(Vec<(Key, Action)>);
+
+impl Keymap {
+    pub fn action(&self, key: &Key) -> Option<Action> {
+        self.0
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, action)| *action)
+    }
+
+    // action of the key event and whether it is pressed
+    pub fn map(&self, event: &KeyEvent) -> Option<(Action, bool)> {
+        match event {
+            KeyEvent::Press(key) => self.action(key).map(|action| (action, true)),
+            KeyEvent::Release(key) => self.action(key).map(|action| (action, false)),
+        }
+    }
+
+    // left side of the keyboard in the versus
+    pub fn player1() -> Keymap {
+        Keymap(vec![
+            (Key::Char('a'), Action::Left),
+            (Key::Char('d'), Action::Right),
+            (Key::Char('s'), Action::SoftDrop),
+            (Key::Char('w'), Action::HardDrop),
+            (Key::Char('c'), Action::RotateLeft),
+            (Key::Char('v'), Action::RotateRight),
+            (Key::Char('x'), Action::Hold),
+            (Key::Char('q'), Action::Quit),
+        ])
+    }
+
+    // right side of the keyboard in the versus
+    pub fn player2() -> Keymap {
+        Keymap(vec![
+            (Key::Left, Action::Left),
+            (Key::Right, Action::Right),
+            (Key::Down, Action::SoftDrop),
+            (Key::Up, Action::HardDrop),
+            (Key::Char(','), Action::RotateLeft),
+            (Key::Char('.'), Action::RotateRight),
+            (Key::Char('/'), Action::Hold),
+        ])
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Keymap {
+        Keymap(vec![
+            (Key::Left, Action::Left),
+            (Key::Right, Action::Right),
+            (Key::Down, Action::SoftDrop),
+            (Key::Up, Action::HardDrop),
+            (Key::Char('z'), Action::RotateLeft),
+            (Key::Char('x'), Action::RotateRight),
+            (Key::Char(' '), Action::Hold),
+            (Key::Char('q'), Action::Quit),
+        ])
+    }
+}
+
+// delayed auto shift, auto repeat rate and soft drop factor
+#[derive(Clone, Copy)]
+pub struct Handling {
+    pub das: u64,
+    pub arr: u64,
+    pub sdf: u64,
+}
+
+impl Default for Handling {
+    fn default() -> Handling {
+        Handling {
+            das: DAS_MSEC,
+            arr: ARR_MSEC,
+            sdf: SOFT_DROP_FACTOR,
+        }
+    }
+}
+
+// buttons held by a player and their auto repeat
+pub struct Controller {
+    handling: Handling,
+    left: bool,
+    right: bool,
+    // direction of the auto shift, -1: left, 1: right
+    shift: isize,
+    das_timer: u64,
+    arr_timer: u64,
+    soft_drop: bool,
+    soft_drop_timer: f64,
+}
+
+impl Controller {
+    pub fn new(handling: Handling) -> Controller {
+        Controller {
+            handling,
+            left: false,
+            right: false,
+            shift: 0,
+            das_timer: 0,
+            arr_timer: 0,
+            soft_drop: false,
+            soft_drop_timer: 0.0,
+        }
+    }
+
+    // press or release the button of the action
+    pub fn input(&mut self, game: &mut Game, action: Action, pressed: bool) -> Result<(), ()> {
+        match (action, pressed) {
+            (Action::Left, _) => {
+                self.left = pressed;
+                self.update_shift(game, -1, pressed);
+            }
+            (Action::Right, _) => {
+                self.right = pressed;
+                self.update_shift(game, 1, pressed);
+            }
+            (Action::SoftDrop, _) => {
+                self.soft_drop = pressed;
+                self.soft_drop_timer = 0.0;
+                if pressed {
+                    shift_block(game, 0, 1);
+                }
+            }
+            (Action::HardDrop, true) => {
+                hard_drop(game);
+                landing(game)?;
+            }
+            (Action::RotateLeft, true) => rotate_left(game),
+            (Action::RotateRight, true) => rotate_right(game),
+            (Action::Hold, true) => hold(game),
+            _ => (),
+        }
+        Ok(())
+    }
+
+    fn update_shift(&mut self, game: &mut Game, direction: isize, pressed: bool) {
+        if pressed {
+            // the last pressed direction wins
+            self.shift = direction;
+            self.das_timer = 0;
+            self.arr_timer = 0;
+            shift_block(game, direction, 0);
+        } else if self.shift == direction {
+            // go back to the other direction if it is still held
+            self.shift = match (self.left, self.right) {
+                (true, _) => -1,
+                (_, true) => 1,
+                _ => 0,
+            };
+            self.das_timer = 0;
+            self.arr_timer = 0;
+        }
+    }
+
+    // advance the auto repeat by 1 millisecond
+    // returns whether the block moved
+    pub fn update(&mut self, game: &mut Game) -> bool {
+        let mut moved = false;
+
+        if self.shift != 0 {
+            if self.das_timer < self.handling.das {
+                self.das_timer += 1;
+            }
+            if self.das_timer >= self.handling.das {
+                if self.handling.arr == 0 {
+                    // move to the wall at once
+                    while shift_block(game, self.shift, 0) {
+                        moved = true;
+                    }
+                } else {
+                    self.arr_timer += 1;
+                    while self.arr_timer >= self.handling.arr {
+                        self.arr_timer -= self.handling.arr;
+                        moved |= shift_block(game, self.shift, 0);
+                    }
+                }
+            }
+        }
+
+        if self.soft_drop {
+            self.soft_drop_timer += 1.0;
+            let interval = gravity_msec(game.level) / self.handling.sdf.max(1) as f64;
+            while self.soft_drop_timer >= interval {
+                self.soft_drop_timer -= interval;
+                if !shift_block(game, 0, 1) {
+                    self.soft_drop_timer = 0.0;
+                    break;
+                }
+                moved = true;
+            }
+        }
+        moved
+    }
+}
+
+// move the block and return whether it moved
+fn shift_block(game: &mut Game, dx: isize, dy: usize) -> bool {
+    let Some(x) = game.piece.pos.x.checked_add_signed(dx) else {
+        return false;
+    };
+    let new_pos = Position {
+        x,
+        y: game.piece.pos.y + dy,
+    };
+    if is_collision(&game.field, &new_pos, game.piece.shape()) {
+        return false;
+    }
+    move_block(game, new_pos);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_csi() {
+        let tests = [
+            (&b""[..], b'D', Some((Key::Left, None))),
+            (b"1;1:3", b'D', Some((Key::Left, Some(false)))),
+            (b"1;1:2", b'C', None),
+            (b"122", b'u', Some((Key::Char('z'), Some(true)))),
+            (b"122;1:3", b'u', Some((Key::Char('z'), Some(false)))),
+            (b"99;5", b'u', Some((Key::Ctrl('c'), Some(true)))),
+            (b"3", b'~', None),
+        ];
+        for (params, last, expect) in tests {
+            assert!(parse_csi(params, last) == expect);
+        }
+    }
+
+    #[test]
+    fn test_auto_shift() {
+        let handling = Handling::default();
+        let mut game = Game::new(0, Box::<crate::randomizer::Bag7>::default());
+        game.piece = Piece::new(crate::blocks::Blockkind::O);
+        let x = game.piece.pos.x;
+        let mut controller = Controller::new(handling);
+
+        controller.input(&mut game, Action::Left, true).ok();
+        assert_eq!(game.piece.pos.x, x - 1);
+        for _ in 0..handling.das {
+            controller.update(&mut game);
+        }
+        assert_eq!(game.piece.pos.x, x - 1);
+        for _ in 0..handling.arr {
+            controller.update(&mut game);
+        }
+        assert_eq!(game.piece.pos.x, x - 2);
+
+        // the last pressed direction wins
+        controller.input(&mut game, Action::Right, true).ok();
+        assert_eq!(game.piece.pos.x, x - 1);
+        controller.input(&mut game, Action::Right, false).ok();
+        controller.input(&mut game, Action::Left, false).ok();
+        for _ in 0..handling.das * 2 {
+            controller.update(&mut game);
+        }
+        assert_eq!(game.piece.pos.x, x - 1);
+    }
+}
@@ -1,41 +1,369 @@
 mod blocks;
 mod game;
 mod play;
+mod input;
+mod randomizer;
+mod record;
+mod rule;
+mod versus;
+mod net;
+mod replay;
+mod fumen;
+mod movegen;
+mod pc;
 mod ai;
 mod ga;
 
-use clap::{Parser, Subcommand};
+use clap::{Args, CommandFactory, Parser, Subcommand};
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
     #[command(subcommand)]
     mode: Option<Mode>,
+
+    /// Milliseconds until a block on the ground is fixed
+    #[arg(long, global = true, default_value_t = game::LOCK_DELAY_MSEC)]
+    lock_delay: u64,
+
+    /// Milliseconds until a held key starts to repeat (DAS),
+    /// only with the key releases of the kitty keyboard protocol
+    #[arg(long, global = true, default_value_t = input::DAS_MSEC)]
+    das: u64,
+
+    /// Milliseconds between repeated moves, 0 to move to the wall at once (ARR),
+    /// only with the key releases of the kitty keyboard protocol
+    #[arg(long, global = true, default_value_t = input::ARR_MSEC)]
+    arr: u64,
+
+    /// Speed of soft drop as a multiple of gravity (SDF),
+    /// only with the key releases of the kitty keyboard protocol
+    #[arg(long, global = true, default_value_t = input::SOFT_DROP_FACTOR)]
+    sdf: u64,
+
+    /// Seed of the block sequence, random if not given
+    #[arg(long, global = true)]
+    seed: Option<u64>,
+
+    /// Generator of the block sequence
+    #[arg(long, global = true, value_enum, default_value_t = randomizer::RandomizerKind::Bag7)]
+    randomizer: randomizer::RandomizerKind,
+
+    /// Broadcast the game to the viewers on this machine at the port (watch connects to 7879 by default),
+    /// not in the versus
+    #[arg(long, global = true)]
+    broadcast: Option<u16>,
+
+    /// File to save the replay of the game, not in the versus [default: ~/.tetrust_replay]
+    #[arg(long, global = true)]
+    replay_file: Option<std::path::PathBuf>,
+
+    /// Start from the field and the block of the fumen (v115@...)
+    #[arg(long, global = true, value_parser = parse_fumen)]
+    fumen: Option<String>,
+
+    /// Show the next placement of a perfect clear by the blocks in sight, not in the versus
+    #[arg(long, global = true)]
+    pc_hint: bool,
 }
 
 #[derive(Subcommand)]
 enum Mode {
     Normal,
-    Auto,
+    /// Clear 40 lines as fast as possible
+    Sprint {
+        /// Lines to clear
+        #[arg(long, default_value_t = 40)]
+        lines: usize,
+    },
+    /// Score as much as possible in a few minutes
+    Ultra {
+        /// Minutes to play
+        #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(u64).range(2..=3))]
+        minutes: u64,
+    },
+    /// Clear lines until the goal
+    Marathon {
+        /// Lines to clear
+        #[arg(long, default_value_t = 150)]
+        lines: usize,
+    },
+    /// Dig through the rows of garbage as fast as possible
+    Dig {
+        /// Rows of garbage
+        #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u64).range(1..=rule::MAX_DIG_ROWS as u64))]
+        rows: u64,
+    },
+    /// Survive the rising garbage as long as possible
+    Survival,
+    /// Two players in one terminal (player 1: wasd c v x, player 2: arrows , . /)
+    Versus,
+    /// Play against the bot
+    VersusAi {
+        /// Blocks placed by the bot per second
+        #[arg(long, default_value_t = 1.0, value_parser = parse_pps)]
+        pps: f64,
+        #[command(flatten)]
+        weights: WeightsArgs,
+        #[command(flatten)]
+        lookahead: LookaheadArgs,
+    },
+    /// Wait for the opponent of the versus over TCP
+    Host {
+        /// Port to listen on
+        #[arg(long, default_value_t = net::PORT)]
+        port: u16,
+    },
+    /// Join the versus of the host over TCP
+    Join {
+        /// Address of the host
+        #[arg(default_value_t = format!("127.0.0.1:{}", net::PORT))]
+        addr: String,
+    },
+    /// Watch the game broadcasted by another process
+    Watch {
+        /// Address of the broadcasting game
+        #[arg(default_value_t = format!("127.0.0.1:{}", net::BROADCAST_PORT))]
+        addr: String,
+    },
+    /// Play back the replay (space: pause, right: step, up/down: speed)
+    Replay {
+        /// Replay file, the last game if not given
+        file: Option<std::path::PathBuf>,
+    },
+    /// Print the placement of the bot for the field given by --fumen
+    Analyze {
+        #[command(flatten)]
+        weights: WeightsArgs,
+        #[command(flatten)]
+        lookahead: LookaheadArgs,
+    },
+    /// Search perfect clears of the field given by --fumen
+    Pc {
+        /// Blocks from the one in play such as "TIOLJSZ" [default: the blocks of the game]
+        #[arg(long, value_parser = parse_queue)]
+        queue: Option<String>,
+        /// Block in the hold
+        #[arg(long, value_parser = parse_hold)]
+        hold: Option<String>,
+        /// Print all the solutions instead of the first one
+        #[arg(long)]
+        all: bool,
+    },
+    Auto {
+        /// Milliseconds between the inputs of the bot
+        #[arg(long, default_value_t = ai::INPUT_DELAY_MSEC)]
+        delay: u64,
+        #[command(flatten)]
+        lookahead: LookaheadArgs,
+    },
     Learning,
 }
 
+// lookahead of the bot
+#[derive(Args)]
+struct LookaheadArgs {
+    /// Blocks the bot looks ahead, from the block in play to the next blocks
+    #[arg(long, default_value_t = ai::LOOKAHEAD.depth as u64,
+          value_parser = clap::value_parser!(u64).range(1..=game::NEXT_LENGTH as u64 + 1))]
+    depth: u64,
+    /// Placements the bot keeps at each depth
+    #[arg(long, default_value_t = ai::LOOKAHEAD.width as u64,
+          value_parser = clap::value_parser!(u64).range(1..))]
+    width: u64,
+}
+
+impl LookaheadArgs {
+    fn build(&self) -> ai::Lookahead {
+        ai::Lookahead {
+            depth: self.depth as usize,
+            width: self.width as usize,
+        }
+    }
+}
+
+// weights of the bot
+#[derive(Args)]
+struct WeightsArgs {
+    /// Weights of the bot: line, max height, height diff, dead space, aggregate height,
+    /// row transitions, column transitions, well sums, landing height, eroded cells
+    #[arg(long, value_parser = parse_weights, default_value_t = Weights(ai::WEIGHTS))]
+    weights: Weights,
+}
+
+impl WeightsArgs {
+    fn build(&self) -> ga::GenoSeq {
+        self.weights.0
+    }
+}
+
+// comma separated weights of each gene
+#[derive(Clone)]
+struct Weights(ga::GenoSeq);
+
+impl std::fmt::Display for Weights {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let weights: Vec<String> = self.0.iter().map(|w| w.to_string()).collect();
+        write!(f, "{}", weights.join(","))
+    }
+}
+
+fn parse_weights(s: &str) -> Result<Weights, String> {
+    let weights = s
+        .split(',')
+        .map(|w| w.trim().parse::<u8>().map_err(|e| e.to_string()))
+        .collect::<Result<Vec<_>, _>>()?;
+    let len = weights.len();
+    weights
+        .try_into()
+        .map(Weights)
+        .map_err(|_| format!("{} weights are needed, but {} given", ga::GenoSeq::default().len(), len))
+}
+
+// blocks per second of the bot in 0.1..=20.0
+fn parse_pps(s: &str) -> Result<f64, String> {
+    let pps = s.trim().parse::<f64>().map_err(|e| e.to_string())?;
+    if (0.1..=20.0).contains(&pps) {
+        Ok(pps)
+    } else {
+        Err("pps is from 0.1 to 20.0".to_string())
+    }
+}
+
+// check the fumen is readable
+fn parse_fumen(s: &str) -> Result<String, String> {
+    match fumen::decode(s) {
+        Some(_) => Ok(s.to_string()),
+        None => Err("not a fumen of v115".to_string()),
+    }
+}
+
+// check the letters of the blocks
+fn parse_queue(s: &str) -> Result<String, String> {
+    match pc::parse_queue(s) {
+        Some(_) => Ok(s.to_string()),
+        None => Err("blocks are I, O, S, Z, J, L and T".to_string()),
+    }
+}
+
+fn parse_hold(s: &str) -> Result<String, String> {
+    match pc::parse_queue(s) {
+        Some(kinds) if kinds.len() == 1 => Ok(s.to_string()),
+        _ => Err("one of I, O, S, Z, J, L and T".to_string()),
+    }
+}
+
+// the versus has no broadcast, replay or hint of a single player game
+fn check_versus(cli: &Cli) {
+    if !matches!(
+        cli.mode,
+        Some(Mode::Versus | Mode::VersusAi { .. } | Mode::Host { .. } | Mode::Join { .. })
+    ) {
+        return;
+    }
+    for (given, flag) in [
+        (cli.broadcast.is_some(), "--broadcast"),
+        (cli.replay_file.is_some(), "--replay-file"),
+        (cli.pc_hint, "--pc-hint"),
+    ] {
+        if given {
+            Cli::command()
+                .error(
+                    clap::error::ErrorKind::ArgumentConflict,
+                    format!("{} cannot be used with the versus", flag),
+                )
+                .exit();
+        }
+    }
+}
 
 fn main() {
     let cli = Cli::parse();
+    check_versus(&cli);
+    let settings = play::Settings {
+        lock_delay: cli.lock_delay,
+        handling: input::Handling {
+            das: cli.das,
+            arr: cli.arr,
+            sdf: cli.sdf,
+        },
+        seed: cli.seed,
+        randomizer: cli.randomizer,
+        broadcast: cli.broadcast,
+        replay_file: cli.replay_file.unwrap_or_else(replay::default_path),
+        fumen: cli.fumen,
+        pc_hint: cli.pc_hint,
+    };
     match cli.mode {
         None |
         Some(Mode::Normal) => {
             // normal Mode
-            play::normal();
+            play::normal(&settings);
+        }
+        Some(Mode::Sprint { lines }) => {
+            // sprint Mode
+            play::sprint(&settings, lines);
+        }
+        Some(Mode::Ultra { minutes }) => {
+            // ultra Mode
+            play::ultra(&settings, minutes);
+        }
+        Some(Mode::Marathon { lines }) => {
+            // marathon Mode
+            play::marathon(&settings, lines);
+        }
+        Some(Mode::Dig { rows }) => {
+            // dig Mode
+            play::dig(&settings, rows as usize);
+        }
+        Some(Mode::Survival) => {
+            // survival Mode
+            play::survival(&settings);
+        }
+        Some(Mode::Versus) => {
+            // versus Mode
+            play::versus(&settings);
+        }
+        Some(Mode::VersusAi {
+            pps,
+            weights,
+            lookahead,
+        }) => {
+            // versus AI Mode
+            let bot = versus::Bot::new(pps, weights.build(), lookahead.build());
+            play::versus_ai(&settings, bot);
+        }
+        Some(Mode::Host { port }) => {
+            // network versus Mode
+            play::host(&settings, port);
+        }
+        Some(Mode::Join { addr }) => {
+            // network versus Mode
+            play::join(&settings, &addr);
+        }
+        Some(Mode::Watch { addr }) => {
+            // spectator Mode
+            play::watch(&addr);
+        }
+        Some(Mode::Replay { file }) => {
+            // replay Mode
+            play::replay(file.as_ref().unwrap_or(&settings.replay_file));
+        }
+        Some(Mode::Analyze { weights, lookahead }) => {
+            // analyze Mode
+            play::analyze(&settings, &weights.build(), lookahead.build());
+        }
+        Some(Mode::Pc { queue, hold, all }) => {
+            // perfect clear Mode
+            play::perfect_clear(&settings, queue.as_deref(), hold.as_deref(), all);
         }
-        Some(Mode::Auto) => {
+        Some(Mode::Auto { delay, lookahead }) => {
             // auto Mode
-            play::auto();
+            play::auto(&settings, lookahead.build(), delay);
         }
         Some(Mode::Learning) => {
             // GA Learning Mode
-            ga::learning();
+            ga::learning(&settings);
         }
     }
 }
@@ -0,0 +1,322 @@
+use crate::blocks::{block_kind, Blockkind, Rotation, ROTATIONS};
+use crate::game::*;
+use crate::input::Action;
+use std::collections::HashSet;
+
+// cells of the block in the field
+pub fn cells(piece: &Piece) -> [(usize, usize); 4] {
+    let shape = piece.shape();
+    let mut cells = [(0, 0); 4];
+    let filled = (0..4)
+        .flat_map(|y| (0..4).map(move |x| (x, y)))
+        .filter(|&(x, y)| shape[y][x] != block_kind::NONE);
+    for (cell, (x, y)) in cells.iter_mut().zip(filled) {
+        *cell = (piece.pos.x + x, piece.pos.y + y);
+    }
+    cells
+}
+
+// a place the block can lock at and the inputs to get there from the spawn
+#[derive(Clone)]
+pub struct Placement {
+    pub piece: Piece,
+    // each input is pressed and released, ending with the hard drop
+    pub path: Vec<Action>,
+}
+
+// a block reached by the search
+struct Node {
+    piece: Piece,
+    from: From,
+    // T block reached by a rotation, a T-spin if it locks there
+    spun: bool,
+}
+
+enum From {
+    // inputs from the spawn
+    Start(Vec<Action>),
+    // index of the previous node and the input
+    Move(usize, Action),
+}
+
+// every placement the block can lock at,
+// reached from the spawn by moves, soft drops and rotations with the wall kicks
+// placements covering the same cells are returned once with the shortest path,
+// except T blocks locked by a rotation into a T-spin and by a move
+pub fn placements(field: &Field, kind: Blockkind) -> Vec<Placement> {
+    explore(field, starts(field, kind), false)
+}
+
+// every placement the block in play can lock at from where it is
+// with the max gravity, the block falls to the ground after every input
+pub fn placements_from(field: &Field, piece: Piece, max_gravity: bool) -> Vec<Placement> {
+    if is_collision(field, &piece.pos, piece.shape()) {
+        return vec![];
+    }
+    explore(field, vec![(piece, vec![])], max_gravity)
+}
+
+// search from the starts in order of the number of inputs
+fn explore(field: &Field, starts: Vec<(Piece, Vec<Action>)>, max_gravity: bool) -> Vec<Placement> {
+    let mut visited = [[[[false; 2]; 4]; FIELD_WIDTH]; FIELD_HEIGHT];
+    let mut nodes = vec![];
+    let mut push = |mut piece: Piece, from: From, nodes: &mut Vec<Node>| {
+        let last = match &from {
+            From::Start(path) => path.last().copied(),
+            From::Move(_, action) => Some(*action),
+        };
+        let mut spun = piece.kind == Blockkind::T
+            && matches!(last, Some(Action::RotateRight | Action::RotateLeft));
+        if max_gravity {
+            while !is_collision(
+                field,
+                &Position {
+                    y: piece.pos.y + 1,
+                    ..piece.pos
+                },
+                piece.shape(),
+            ) {
+                piece.pos.y += 1;
+                // the fall after the rotation is not a T-spin
+                spun = false;
+            }
+        }
+        let seen = &mut visited[piece.pos.y][piece.pos.x][piece.rotation as usize][spun as usize];
+        if !*seen {
+            *seen = true;
+            nodes.push(Node { piece, from, spun });
+        }
+    };
+    for (piece, path) in starts {
+        push(piece, From::Start(path), &mut nodes);
+    }
+
+    let mut found = HashSet::new();
+    let mut placements = vec![];
+    // nodes are searched in order of the number of inputs
+    let mut i = 0;
+    while let Some(&Node { piece, spun, .. }) = nodes.get(i) {
+        let moves = [
+            (-1, 0, Action::Left),
+            (1, 0, Action::Right),
+            (0, 1, Action::SoftDrop),
+        ]
+        .map(|(dx, dy, action)| {
+            let pos = Position {
+                x: piece.pos.x.checked_add_signed(dx)?,
+                y: piece.pos.y + dy,
+            };
+            (!is_collision(field, &pos, piece.shape())).then_some((Piece { pos, ..piece }, action))
+        });
+        if moves[2].is_none() {
+            // on the ground
+            let mut key = cells(&piece);
+            key.sort();
+            // T-spins are told apart by the rotation and the last input
+            let corners = tspin_corners(field, &piece).iter().filter(|&&c| c).count();
+            let spin = (piece.kind == Blockkind::T)
+                .then_some((piece.rotation as usize, spun && corners >= 3));
+            if found.insert((key, spin)) {
+                placements.push(Placement {
+                    piece,
+                    path: path(&nodes, i),
+                });
+            }
+        }
+        let rotations = [(0, Action::RotateRight), (1, Action::RotateLeft)]
+            .map(|(direction, action)| rotated(field, &piece, direction).map(|r| (r.0, action)));
+        for (next, action) in moves.into_iter().chain(rotations).flatten() {
+            push(next, From::Move(i, action), &mut nodes);
+        }
+        i += 1;
+    }
+    placements
+}
+
+// inputs to the node followed by the hard drop
+fn path(nodes: &[Node], mut i: usize) -> Vec<Action> {
+    let mut path = vec![];
+    loop {
+        match &nodes[i].from {
+            From::Start(start) => {
+                path.extend(start.iter().rev());
+                break;
+            }
+            From::Move(prev, action) => {
+                path.push(*action);
+                i = *prev;
+            }
+        }
+    }
+    path.reverse();
+    // the hard drop does the last soft drops
+    while path.last() == Some(&Action::SoftDrop) {
+        path.pop();
+    }
+    path.push(Action::HardDrop);
+    path
+}
+
+// where the search starts and the inputs to get there
+// above the stack every rotation and column is reached by rotating at the spawn,
+// moving sideways and falling, so the search starts there instead of the spawn
+fn starts(field: &Field, kind: Blockkind) -> Vec<(Piece, Vec<Action>)> {
+    let spawn = Piece::new(kind);
+    if is_collision(field, &spawn.pos, spawn.shape()) {
+        return vec![];
+    }
+    let top = field[..FIELD_HEIGHT - 2]
+        .iter()
+        .position(|row| {
+            row[2..FIELD_WIDTH - 2]
+                .iter()
+                .any(|&c| c != block_kind::NONE)
+        })
+        .unwrap_or(FIELD_HEIGHT - 2);
+    // the 4x4 box of the block stays above the stack
+    if top < spawn.pos.y + 4 + 4 {
+        return vec![(spawn, vec![])];
+    }
+    let y = top - 4;
+    ROTATIONS
+        .iter()
+        .flat_map(|&rotation| {
+            (0..FIELD_WIDTH).map(move |x| Piece {
+                kind,
+                rotation,
+                pos: Position { x, y },
+            })
+        })
+        // the cells out of the field are not checked by is_collision
+        .filter(|piece| {
+            cells(piece)
+                .iter()
+                .all(|c| (2..FIELD_WIDTH - 2).contains(&c.0))
+        })
+        .filter(|piece| !is_collision(field, &piece.pos, piece.shape()))
+        .map(|piece| {
+            let rotate = match piece.rotation {
+                Rotation::Spawn => vec![],
+                Rotation::Right => vec![Action::RotateRight],
+                Rotation::Reverse => vec![Action::RotateRight; 2],
+                Rotation::Left => vec![Action::RotateLeft],
+            };
+            let shift = if piece.pos.x < spawn.pos.x {
+                vec![Action::Left; spawn.pos.x - piece.pos.x]
+            } else {
+                vec![Action::Right; piece.pos.x - spawn.pos.x]
+            };
+            let fall = vec![Action::SoftDrop; y - spawn.pos.y];
+            (piece, [rotate, shift, fall].concat())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blocks::BLOCK_KINDS;
+    use crate::input::{Controller, Handling};
+    use crate::randomizer::Bag7;
+
+    #[test]
+    fn test_placements() {
+        let mut game = Game::new(0, Box::<Bag7>::default());
+        // 11 columns and 2 orientations
+        assert_eq!(placements(&game.field, Blockkind::I).len(), 8 + 11);
+        assert_eq!(placements(&game.field, Blockkind::O).len(), 10);
+        assert_eq!(placements(&game.field, Blockkind::T).len(), 9 * 2 + 10 * 2);
+
+        // a slot under the overhang is reached by a slide
+        for y in [FIELD_HEIGHT - 4, FIELD_HEIGHT - 3] {
+            for x in 6..FIELD_WIDTH - 2 {
+                game.field[y][x] = block_kind::GARBAGE;
+            }
+        }
+        game.field[FIELD_HEIGHT - 5][2] = block_kind::GARBAGE;
+        game.field[FIELD_HEIGHT - 5][3] = block_kind::GARBAGE;
+        let tuck = placements(&game.field, Blockkind::O)
+            .into_iter()
+            .any(|placement| cells(&placement.piece).contains(&(2, FIELD_HEIGHT - 3)));
+        assert!(tuck);
+
+        // the paths put the block there by the inputs of the game
+        let handling = Handling::default();
+        for kind in BLOCK_KINDS {
+            for placement in placements(&game.field, kind) {
+                let mut played = game.clone();
+                played.piece = Piece::new(kind);
+                let mut controller = Controller::new(handling);
+                for &action in &placement.path {
+                    controller.input(&mut played, action, true).ok();
+                    controller.input(&mut played, action, false).ok();
+                }
+                let mut expected = game.clone();
+                expected.piece = placement.piece;
+                fix_block(&mut expected);
+                erase_line(&mut expected.field);
+                assert_eq!(played.field, expected.field);
+            }
+        }
+    }
+
+    #[test]
+    fn test_tspin_placement() {
+        let mut game = Game::new(0, Box::<Bag7>::default());
+        for x in 2..FIELD_WIDTH - 2 {
+            if x != 5 {
+                game.field[19][x] = block_kind::I;
+            }
+            if !(4..=6).contains(&x) {
+                game.field[18][x] = block_kind::I;
+            }
+        }
+        // overhang of the T slot
+        game.field[17][4] = block_kind::I;
+
+        // T-spin of the block locked by the path
+        let play = |game: &Game, path: &[Action]| {
+            let handling = Handling::default();
+            let mut game = game.clone();
+            game.piece = Piece::new(Blockkind::T);
+            let mut controller = Controller::new(handling);
+            for &action in &path[..path.len() - 1] {
+                controller.input(&mut game, action, true).ok();
+                controller.input(&mut game, action, false).ok();
+            }
+            hard_drop(&mut game);
+            tspin(&game)
+        };
+        // placements covering the cells
+        let covering = |game: &Game, slot: [(usize, usize); 4]| {
+            placements(&game.field, Blockkind::T)
+                .into_iter()
+                .filter(|placement| {
+                    let mut cells = cells(&placement.piece);
+                    cells.sort();
+                    cells == slot
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // the block is rotated into the slot
+        let slot = covering(&game, [(4, 18), (5, 18), (5, 19), (6, 18)]);
+        assert_eq!(slot.len(), 1);
+        let path = &slot[0].path;
+        assert!(matches!(
+            path[path.len() - 2],
+            Action::RotateRight | Action::RotateLeft
+        ));
+        assert!(play(&game, path) == TSpin::Full);
+
+        // the hole at the wall is reached by a drop and by a rotation on the ground
+        let mut game = Game::new(0, Box::<Bag7>::default());
+        for x in 2..FIELD_WIDTH - 3 {
+            game.field[19][x] = block_kind::I;
+        }
+        let slot = covering(&game, [(11, 18), (12, 17), (12, 18), (12, 19)]);
+        assert_eq!(slot.len(), 2);
+        assert!(play(&game, &slot[0].path) == TSpin::None);
+        assert!(play(&game, &slot[1].path) == TSpin::Mini);
+    }
+}
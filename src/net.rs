@@ -0,0 +1,423 @@
+use crate::blocks::{Blockkind, BLOCK_KINDS, COLOR_TABLE, ROTATIONS};
+use crate::game::*;
+use crate::movegen::cells;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc;
+use std::thread;
+
+pub const PORT: u16 = 7878;
+pub const BROADCAST_PORT: u16 = 7879;
+
+// messages between the players, one line each
+pub enum Message {
+    // blocks of the match, sent by the host
+    Seed(u64),
+    // board of the sender to draw
+    State(Box<Snapshot>),
+    // garbage lines sent to the receiver
+    Garbage(usize),
+    // the sender topped out
+    Lose,
+}
+
+impl Message {
+    pub fn encode(&self) -> String {
+        match self {
+            Message::Seed(seed) => format!("seed {}", seed),
+            Message::State(snapshot) => format!("state {}", snapshot.encode()),
+            Message::Garbage(lines) => format!("garbage {}", lines),
+            Message::Lose => "lose".to_string(),
+        }
+    }
+
+    pub fn decode(line: &str) -> Option<Message> {
+        let (name, args) = line.split_once(' ').unwrap_or((line, ""));
+        match name {
+            "seed" => Some(Message::Seed(args.parse().ok()?)),
+            "state" => Some(Message::State(Box::new(Snapshot::decode(args)?))),
+            // no more rows than the playfield
+            "garbage" => Some(Message::Garbage(
+                args.parse()
+                    .ok()
+                    .filter(|&lines| lines <= FIELD_HEIGHT - 2)?,
+            )),
+            "lose" => Some(Message::Lose),
+            _ => None,
+        }
+    }
+}
+
+// what the opponent needs to draw the board of a player
+pub struct Snapshot {
+    field: Field,
+    piece: Piece,
+    hold: Option<Blockkind>,
+    next: Vec<Blockkind>,
+    score: usize,
+    line: usize,
+    level: usize,
+    pending: usize,
+}
+
+impl Snapshot {
+    pub fn new(game: &Game, pending: usize) -> Snapshot {
+        Snapshot {
+            field: game.field,
+            piece: game.piece,
+            hold: game.hold,
+            next: game.next.iter().take(NEXT_LENGTH).copied().collect(),
+            score: game.score,
+            line: game.line,
+            level: game.level,
+            pending,
+        }
+    }
+
+    // copy the board to the game of the opponent, returns the pending garbage
+    pub fn apply(&self, game: &mut Game) -> usize {
+        game.field = self.field;
+        game.piece = self.piece;
+        game.hold = self.hold;
+        game.next = self.next.iter().copied().collect();
+        game.score = self.score;
+        game.line = self.line;
+        game.level = self.level;
+        self.pending
+    }
+
+    // rows of the playfield in hex digits, then the numbers separated by spaces
+    fn encode(&self) -> String {
+        let rows: Vec<String> = self.field[..FIELD_HEIGHT - 2]
+            .iter()
+            .map(|row| {
+                row[2..FIELD_WIDTH - 2]
+                    .iter()
+                    .map(|&c| char::from_digit(c as u32, 16).unwrap_or('0'))
+                    .collect()
+            })
+            .collect();
+        let next: String = self
+            .next
+            .iter()
+            .map(|&kind| kind_char(Some(kind)))
+            .collect();
+        format!(
+            "{} {} {} {} {} {} {} {} {} {} {}",
+            rows.join("/"),
+            self.piece.kind as usize,
+            self.piece.rotation as usize,
+            self.piece.pos.x,
+            self.piece.pos.y,
+            kind_char(self.hold),
+            next,
+            self.score,
+            self.line,
+            self.level,
+            self.pending
+        )
+    }
+
+    fn decode(args: &str) -> Option<Snapshot> {
+        let mut args = args.split(' ');
+        let mut field = empty_field();
+        let mut rows = args.next()?.split('/');
+        for row in field[..FIELD_HEIGHT - 2].iter_mut() {
+            let cells = rows.next()?;
+            if cells.len() != PLAYFIELD_WIDTH {
+                return None;
+            }
+            for (cell, c) in row[2..FIELD_WIDTH - 2].iter_mut().zip(cells.chars()) {
+                *cell = c.to_digit(16)? as usize;
+                if *cell >= COLOR_TABLE.len() {
+                    return None;
+                }
+            }
+        }
+        if rows.next().is_some() {
+            return None;
+        }
+        let mut number = || args.next()?.parse::<usize>().ok();
+        let piece = Piece {
+            kind: *BLOCK_KINDS.get(number()?)?,
+            rotation: *ROTATIONS.get(number()?)?,
+            pos: Position {
+                x: number()?,
+                y: number()?,
+            },
+        };
+        // the piece is drawn in the playfield without overlapping the blocks
+        let inside = piece.pos.x < FIELD_WIDTH
+            && piece.pos.y < FIELD_HEIGHT
+            && cells(&piece)
+                .iter()
+                .all(|&(x, y)| (2..FIELD_WIDTH - 2).contains(&x) && y < FIELD_HEIGHT - 2);
+        if !inside || is_collision(&field, &piece.pos, piece.shape()) {
+            return None;
+        }
+        let hold = kind_of(args.next()?.chars().next()?)?;
+        let next = args
+            .next()?
+            .chars()
+            .map(kind_of)
+            .collect::<Option<Option<Vec<_>>>>()??;
+        let mut number = || args.next()?.parse::<usize>().ok();
+        Some(Snapshot {
+            field,
+            piece,
+            hold,
+            next,
+            score: number()?,
+            line: number()?,
+            level: number()?,
+            pending: number()?,
+        })
+    }
+}
+
+// index digit of the block, '-' for none
+fn kind_char(kind: Option<Blockkind>) -> char {
+    match kind {
+        Some(kind) => char::from_digit(kind as u32, 10).unwrap_or('-'),
+        None => '-',
+    }
+}
+
+// Some(None) for '-', None if the character is invalid
+fn kind_of(c: char) -> Option<Option<Blockkind>> {
+    match c {
+        '-' => Some(None),
+        c => Some(Some(*BLOCK_KINDS.get(c.to_digit(10)? as usize)?)),
+    }
+}
+
+// connection to the opponent
+pub struct Connection {
+    stream: TcpStream,
+    // None when the connection is closed
+    messages: mpsc::Receiver<Option<Message>>,
+}
+
+impl Connection {
+    // wait for the opponent to join
+    pub fn host(port: u16) -> io::Result<Connection> {
+        let listener = TcpListener::bind(("0.0.0.0", port))?;
+        let (stream, _) = listener.accept()?;
+        Connection::new(stream)
+    }
+
+    pub fn join(addr: impl ToSocketAddrs) -> io::Result<Connection> {
+        Connection::new(TcpStream::connect(addr)?)
+    }
+
+    fn new(stream: TcpStream) -> io::Result<Connection> {
+        stream.set_nodelay(true)?;
+        let reader = BufReader::new(stream.try_clone()?);
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            for line in reader.lines().map_while(Result::ok) {
+                if let Some(message) = Message::decode(&line) {
+                    if tx.send(Some(message)).is_err() {
+                        return;
+                    }
+                }
+            }
+            tx.send(None).ok();
+        });
+        Ok(Connection {
+            stream,
+            messages: rx,
+        })
+    }
+
+    pub fn send(&mut self, message: &Message) -> io::Result<()> {
+        writeln!(self.stream, "{}", message.encode())
+    }
+
+    pub fn recv(&self) -> Option<Message> {
+        self.messages.recv().ok().flatten()
+    }
+
+    pub fn try_iter(&self) -> mpsc::TryIter<'_, Option<Message>> {
+        self.messages.try_iter()
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        // stop the reader of both sides
+        self.stream.shutdown(Shutdown::Both).ok();
+    }
+}
+
+// viewers watching the game, the game is not affected by them
+pub struct Broadcaster {
+    listener: TcpListener,
+    viewers: Vec<TcpStream>,
+    seed: u64,
+}
+
+impl Broadcaster {
+    // only the viewers on this machine can connect
+    pub fn new(port: u16, seed: u64) -> io::Result<Broadcaster> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        listener.set_nonblocking(true)?;
+        Ok(Broadcaster {
+            listener,
+            viewers: vec![],
+            seed,
+        })
+    }
+
+    // send the message to the viewers, new viewers get the seed first
+    // the streams never block, a viewer not keeping up is dropped
+    pub fn send(&mut self, message: &Message) {
+        while let Ok((mut stream, _)) = self.listener.accept() {
+            if stream.set_nonblocking(true).is_ok()
+                && writeln!(stream, "{}", Message::Seed(self.seed).encode()).is_ok()
+            {
+                self.viewers.push(stream);
+            }
+        }
+        let line = message.encode();
+        // viewers gone or stalled are dropped
+        self.viewers
+            .retain_mut(|stream| writeln!(stream, "{}", line).is_ok());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blocks::block_kind;
+    use crate::input::Handling;
+    use crate::randomizer::Bag7;
+    use crate::versus::Player;
+
+    #[test]
+    fn test_loopback() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut guest = Connection::join(listener.local_addr().unwrap()).unwrap();
+        let mut host = Connection::new(listener.accept().unwrap().0).unwrap();
+
+        host.send(&Message::Seed(42)).unwrap();
+        assert!(matches!(guest.recv(), Some(Message::Seed(42))));
+
+        let handling = Handling::default();
+        let mut player = Player::new(Game::new(3, Box::<Bag7>::default()), handling);
+        player.game.fill_garbage(4);
+        player.game.hold = Some(Blockkind::T);
+        player.game.score = 1200;
+        player.receive(2);
+        guest
+            .send(&Message::State(Box::new(Snapshot::new(
+                &player.game,
+                player.pending(),
+            ))))
+            .unwrap();
+        let Some(Message::State(snapshot)) = host.recv() else {
+            panic!("state is not received");
+        };
+        let mut opponent = Game::new(3, Box::<Bag7>::default());
+        assert_eq!(snapshot.apply(&mut opponent), 2);
+        assert_eq!(opponent.field, player.game.field);
+        assert!(opponent.hold == Some(Blockkind::T));
+        assert!(opponent
+            .next
+            .iter()
+            .eq(player.game.next.iter().take(NEXT_LENGTH)));
+        assert_eq!(opponent.score, 1200);
+
+        drop(guest);
+        assert!(host.recv().is_none());
+    }
+
+    #[test]
+    fn test_huge_garbage() {
+        assert!(matches!(
+            Message::decode(&format!("garbage {}", FIELD_HEIGHT - 2)),
+            Some(Message::Garbage(20))
+        ));
+        for bad in ["garbage 30", "garbage 18446744073709551615"] {
+            assert!(Message::decode(bad).is_none(), "{bad}");
+        }
+
+        // the receiver tops out instead of slicing past the field
+        let mut game = Game::new(0, Box::<Bag7>::default());
+        assert!(insert_garbage(&mut game, 30, 0).is_err());
+    }
+
+    #[test]
+    fn test_bad_snapshot() {
+        let mut game = Game::new(0, Box::<Bag7>::default());
+        game.field[FIELD_HEIGHT - 3][2] = block_kind::GARBAGE;
+        let text = Message::State(Box::new(Snapshot::new(&game, 0))).encode();
+        assert!(Message::decode(&text).is_some());
+
+        // the arguments after "state": rows, kind, rotation, x, y, ...
+        let replaced = |i: usize, arg: &str| {
+            let mut args: Vec<&str> = text.split(' ').collect();
+            args[i + 1] = arg;
+            args.join(" ")
+        };
+        let rows: Vec<&str> = text.split(' ').nth(1).unwrap().split('/').collect();
+        for bad in [
+            // a short row
+            replaced(
+                0,
+                &rows[1..].join("/").replacen("00000000000", "0000000000", 1),
+            ),
+            // a missing row
+            replaced(0, &rows[1..].join("/")),
+            // a color out of the table
+            replaced(0, &rows.join("/").replacen('0', "f", 1)),
+            // the piece out of the field
+            replaced(3, "14"),
+            replaced(4, &FIELD_HEIGHT.to_string()),
+            replaced(4, &(FIELD_HEIGHT - 3).to_string()),
+        ] {
+            assert!(Message::decode(&bad).is_none(), "{bad}");
+        }
+
+        // the piece overlapping the blocks
+        let mut piece = Piece::new(Blockkind::O);
+        piece.pos.x = 1;
+        piece.pos.y = FIELD_HEIGHT - 4;
+        assert!(is_collision(&game.field, &piece.pos, piece.shape()));
+        game.piece = piece;
+        let text = Message::State(Box::new(Snapshot::new(&game, 0))).encode();
+        assert!(Message::decode(&text).is_none());
+    }
+
+    #[test]
+    fn test_broadcast() {
+        let mut broadcaster = Broadcaster::new(0, 9).unwrap();
+        let viewer = Connection::join(broadcaster.listener.local_addr().unwrap()).unwrap();
+        // the viewer is accepted when something is sent
+        while broadcaster.viewers.is_empty() {
+            broadcaster.send(&Message::Lose);
+        }
+        assert!(matches!(viewer.recv(), Some(Message::Seed(9))));
+        assert!(matches!(viewer.recv(), Some(Message::Lose)));
+    }
+
+    #[test]
+    fn test_stalled_viewer() {
+        let mut broadcaster = Broadcaster::new(0, 9).unwrap();
+        // the viewer never reads
+        let _viewer = TcpStream::connect(broadcaster.listener.local_addr().unwrap()).unwrap();
+        while broadcaster.viewers.is_empty() {
+            broadcaster.send(&Message::Lose);
+        }
+        let game = Game::new(0, Box::<Bag7>::default());
+        let state = Message::State(Box::new(Snapshot::new(&game, 0)));
+        // the viewer is dropped when the buffers are full instead of blocking the game
+        for _ in 0..1_000_000 {
+            if broadcaster.viewers.is_empty() {
+                break;
+            }
+            broadcaster.send(&state);
+        }
+        assert!(broadcaster.viewers.is_empty());
+    }
+}
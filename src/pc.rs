@@ -0,0 +1,321 @@
+use crate::blocks::{block_kind, Blockkind, BLOCK_KINDS};
+use crate::game::*;
+use crate::movegen::{cells, placements, Placement};
+use crate::rule::KIND_NAMES;
+use std::collections::HashSet;
+
+// row below the bottom of the playfield
+const FLOOR: usize = FIELD_HEIGHT - 2;
+// fields searched for the hint in the game
+pub const HINT_LIMIT: usize = 10000;
+
+// placements clearing the field, in order
+pub type Solution = Vec<Piece>;
+
+// perfect clear search by the blocks in sight
+pub struct Solver {
+    // the block in play followed by the next blocks
+    pub pieces: Vec<Blockkind>,
+    pub hold: Option<Blockkind>,
+    // the hold is not used yet for the block in play
+    pub can_hold: bool,
+    // stop at the first solution
+    pub first: bool,
+    // number of fields to search from, None for no limit
+    pub limit: Option<usize>,
+}
+
+impl Solver {
+    // the block in play, the hold and the next blocks of the game
+    pub fn new(game: &Game, next: usize) -> Solver {
+        Solver {
+            pieces: [game.piece.kind]
+                .into_iter()
+                .chain(game.next.iter().take(next).copied())
+                .collect(),
+            hold: game.hold,
+            can_hold: !game.holded,
+            first: true,
+            limit: None,
+        }
+    }
+
+    // solutions of the lowest heights first
+    pub fn solve(&self, field: &Field) -> Vec<Solution> {
+        let filled = field[..FLOOR]
+            .iter()
+            .map(|row| row[2..FIELD_WIDTH - 2].iter().filter(|&&c| c != 0).count())
+            .sum::<usize>();
+        let top = field[..FLOOR]
+            .iter()
+            .position(|row| row[2..FIELD_WIDTH - 2].iter().any(|&c| c != 0))
+            .map_or(0, |y| FLOOR - y);
+        let usable = self.pieces.len() + self.hold.is_some() as usize;
+
+        let mut search = Search {
+            solver: self,
+            game: Game::new(0, Box::<crate::randomizer::Bag7>::default()),
+            path: vec![],
+            solutions: vec![],
+            dead: HashSet::new(),
+            nodes: 0,
+        };
+        for rows in top.max(1)..=FLOOR {
+            // the empty cells are filled by the blocks
+            let empty = rows * PLAYFIELD_WIDTH - filled;
+            if !empty.is_multiple_of(4) || !fillable(field, rows) {
+                continue;
+            }
+            if empty / 4 > usable {
+                break;
+            }
+            search.search(field, rows, 0, self.hold, self.can_hold);
+            if self.first && !search.solutions.is_empty() {
+                break;
+            }
+        }
+        search.solutions
+    }
+}
+
+struct Search<'a> {
+    solver: &'a Solver,
+    // the block is fixed and the lines are erased in the field of this game
+    game: Game,
+    path: Solution,
+    solutions: Vec<Solution>,
+    // fields, blocks and hold known to have no solution
+    dead: HashSet<(Vec<u16>, usize, Option<usize>, bool)>,
+    nodes: usize,
+}
+
+impl Search<'_> {
+    // search placements filling the bottom rows, returns whether any solution is found
+    // the block of the index is in play
+    fn search(
+        &mut self,
+        field: &Field,
+        rows: usize,
+        index: usize,
+        hold: Option<Blockkind>,
+        can_hold: bool,
+    ) -> bool {
+        if rows == 0 {
+            self.solutions.push(self.path.clone());
+            return true;
+        }
+        let key = (
+            field[FLOOR - rows..FLOOR]
+                .iter()
+                .map(|row| {
+                    row[2..FIELD_WIDTH - 2]
+                        .iter()
+                        .fold(0, |bits, &c| bits << 1 | (c != 0) as u16)
+                })
+                .collect(),
+            index,
+            hold.map(|kind| kind as usize),
+            can_hold,
+        );
+        if self.dead.contains(&key) || self.is_limited() {
+            return false;
+        }
+        self.nodes += 1;
+
+        // block to place, the block in play after it and the hold after it
+        let Some(&current) = self.solver.pieces.get(index) else {
+            return false;
+        };
+        let mut choices = vec![(current, index + 1, hold)];
+        if can_hold {
+            match hold {
+                Some(kind) if kind != current => choices.push((kind, index + 1, Some(current))),
+                Some(_) => (),
+                None => {
+                    if let Some(&next) = self.solver.pieces.get(index + 1) {
+                        choices.push((next, index + 2, Some(current)));
+                    }
+                }
+            }
+        }
+
+        let mut found = false;
+        for (kind, index, hold) in choices {
+            for Placement { piece, .. } in placements(field, kind) {
+                if cells(&piece).iter().any(|&(_, y)| y < FLOOR - rows) {
+                    continue;
+                }
+                self.game.field = *field;
+                self.game.piece = piece;
+                fix_block(&mut self.game);
+                let rows = rows - erase_line(&mut self.game.field);
+                let next = self.game.field;
+                if !fillable(&next, rows) {
+                    continue;
+                }
+                self.path.push(piece);
+                found |= self.search(&next, rows, index, hold, true);
+                self.path.pop();
+                if found && self.solver.first {
+                    return true;
+                }
+            }
+        }
+        if !found && !self.is_limited() {
+            self.dead.insert(key);
+        }
+        found
+    }
+
+    fn is_limited(&self) -> bool {
+        self.solver.limit.is_some_and(|limit| self.nodes >= limit)
+    }
+}
+
+// each area of the empty cells in the bottom rows can be filled by whole blocks
+// areas joined by erasing a line in between are not considered
+fn fillable(field: &Field, rows: usize) -> bool {
+    let mut visited = [[false; FIELD_WIDTH]; FIELD_HEIGHT];
+    for y in FLOOR - rows..FLOOR {
+        for x in 2..FIELD_WIDTH - 2 {
+            if visited[y][x] || field[y][x] != block_kind::NONE {
+                continue;
+            }
+            visited[y][x] = true;
+            let mut stack = vec![(x, y)];
+            let mut size = 0usize;
+            while let Some((x, y)) = stack.pop() {
+                size += 1;
+                for (x, y) in [(x - 1, y), (x + 1, y), (x, y.wrapping_sub(1)), (x, y + 1)] {
+                    if (FLOOR - rows..FLOOR).contains(&y)
+                        && !visited[y][x]
+                        && field[y][x] == block_kind::NONE
+                    {
+                        visited[y][x] = true;
+                        stack.push((x, y));
+                    }
+                }
+            }
+            if !size.is_multiple_of(4) {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+// blocks by the letters such as "TIOLJSZ"
+pub fn parse_queue(s: &str) -> Option<Vec<Blockkind>> {
+    s.chars()
+        .map(|c| {
+            let i = KIND_NAMES
+                .iter()
+                .position(|&n| n == c.to_ascii_uppercase())?;
+            Some(BLOCK_KINDS[i])
+        })
+        .collect()
+}
+
+// rows of the field with the letters of the blocks placed
+// the blocks are drawn where they are placed before the lines are erased
+pub fn format_solution(game: &Game, solution: &Solution) -> String {
+    let mut game = game.clone();
+    let filled = game.field[..FLOOR]
+        .iter()
+        .map(|row| row[2..FIELD_WIDTH - 2].iter().filter(|&&c| c != 0).count())
+        .sum::<usize>();
+    let height = (filled + solution.len() * 4) / PLAYFIELD_WIDTH;
+    let mut text: Vec<Vec<char>> = game.field[FLOOR - height..FLOOR]
+        .iter()
+        .map(|row| {
+            row[2..FIELD_WIDTH - 2]
+                .iter()
+                .map(|&c| if c == block_kind::NONE { '.' } else { '#' })
+                .collect()
+        })
+        .collect();
+    // row of the text for each row left in the field
+    let mut lines: Vec<usize> = (0..height).collect();
+
+    for &piece in solution {
+        let top = FLOOR - lines.len();
+        for (x, y) in cells(&piece) {
+            text[lines[y - top]][x - 2] = KIND_NAMES[piece.kind as usize];
+        }
+        game.piece = piece;
+        fix_block(&mut game);
+        for y in (top..FLOOR).rev() {
+            if game.field[y][2..FIELD_WIDTH - 2].iter().all(|&c| c != 0) {
+                lines.remove(y - top);
+            }
+        }
+        erase_line(&mut game.field);
+    }
+    text.iter()
+        .map(|row| row.iter().collect::<String>() + "\n")
+        .collect()
+}
+
+// the first placement of the solution drawn over the field
+pub fn draw_hint(game: &Game, solution: Option<&Solution>, left: usize) {
+    let first = solution.and_then(|solution| solution.first());
+    if let Some(piece) = first {
+        for (x, y) in cells(piece) {
+            print!("\x1b[{};{}H\x1b[0m<>", y + 2, left + 1 + (x - 1) * 2);
+        }
+    }
+    let text = match (solution, first) {
+        (Some(solution), Some(piece)) if piece.kind != game.piece.kind => {
+            format!("PC {} HOLD", solution.len())
+        }
+        (Some(solution), _) => format!("PC {}", solution.len()),
+        (None, _) => String::new(),
+    };
+    println!("\x1b[0m\x1b[19;{}H{:<18}", left + 38, text);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::randomizer::Bag7;
+
+    #[test]
+    fn test_perfect_clear() {
+        let mut game = Game::new(0, Box::<Bag7>::default());
+        // 2 rows open at the left 4 columns
+        for y in FLOOR - 2..FLOOR {
+            for x in 6..FIELD_WIDTH - 2 {
+                game.field[y][x] = block_kind::GARBAGE;
+            }
+        }
+        let mut solver = Solver {
+            pieces: parse_queue("oio").unwrap(),
+            hold: None,
+            can_hold: true,
+            first: false,
+            limit: None,
+        };
+        // 2 O blocks with the I in the hold
+        let solutions = solver.solve(&game.field);
+        assert!(!solutions.is_empty());
+        for solution in &solutions {
+            assert_eq!(solution.len(), 2);
+            let mut game = game.clone();
+            for &piece in solution {
+                game.piece = piece;
+                fix_block(&mut game);
+                erase_line(&mut game.field);
+            }
+            assert!(is_perfect_clear(&game.field));
+        }
+
+        assert_eq!(
+            format_solution(&game, &solutions[0]),
+            "OOOO#######\nOOOO#######\n"
+        );
+
+        // not enough blocks
+        solver.pieces = parse_queue("oi").unwrap();
+        assert!(solver.solve(&game.field).is_empty());
+    }
+}
@@ -1,138 +1,647 @@
-use crate::ai::eval;
+use crate::ai::{search, AutoPlayer, Lookahead, WEIGHTS};
+use crate::fumen;
+use crate::ga::GenoSeq;
 use crate::game::*;
-use getch_rs::{Getch, Key};
-use std::sync::{Arc, Mutex};
+use crate::input::{Action, Controller, Handling, Input, KeyEvent, Keymap};
+use crate::net::{Broadcaster, Connection, Message, Snapshot};
+use crate::pc::{self, Solution, Solver};
+use crate::randomizer::RandomizerKind;
+use crate::replay::{action_name, Playback, Replay};
+use crate::rule::{tick, End, Progress, Rule};
+use crate::versus::{draw_meter, draw_players, send_garbage, Bot, Player, PLAYER_LEFT};
+use getch_rs::Key;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
 use std::{thread, time};
 
-pub fn normal() {
-    let game = Arc::new(Mutex::new(Game::new()));
+// speed of the replay
+const REPLAY_SPEED_MAX: f64 = 16.0;
+const REPLAY_SPEED_MIN: f64 = 0.125;
 
-    println!("\x1b[2J\x1b[H\x1b[?25l");
+// settings of the game given by the command line
+pub struct Settings {
+    pub lock_delay: u64,
+    pub handling: Handling,
+    pub seed: Option<u64>,
+    pub randomizer: RandomizerKind,
+    // port to broadcast the game to the viewers
+    pub broadcast: Option<u16>,
+    // file to save the replay of the game
+    pub replay_file: PathBuf,
+    // fumen of the field to start from
+    pub fumen: Option<String>,
+    // show the perfect clear in the game
+    pub pc_hint: bool,
+}
 
-    draw(&game.lock().unwrap());
+impl Settings {
+    // new game with the seed given by the command line or a random one
+    pub fn new_game(&self) -> Game {
+        self.new_game_with_seed(self.seed.unwrap_or_else(rand::random))
+    }
 
-    {
-        let game = Arc::clone(&game);
+    // broadcaster of the game if --broadcast is given
+    pub fn broadcaster(&self, seed: u64) -> Result<Option<Broadcaster>, ()> {
+        let Some(port) = self.broadcast else {
+            return Ok(None);
+        };
+        match Broadcaster::new(port, seed) {
+            Ok(broadcaster) => Ok(Some(broadcaster)),
+            Err(e) => {
+                eprintln!("failed to broadcast on port {}: {}", port, e);
+                Err(())
+            }
+        }
+    }
 
-        let _ = thread::spawn(move || loop {
-            let sleep_msec =
-                match 1000u64.saturating_sub((game.lock().unwrap().line as u64 / 10) * 100) {
-                    0 => 100,
-                    msec => msec,
-                };
-            thread::sleep(time::Duration::from_millis(sleep_msec));
+    // the records are kept for the games of the random 7 bag from the empty field
+    pub fn ranked(&self) -> bool {
+        self.seed.is_none()
+            && self.fumen.is_none()
+            && matches!(self.randomizer, RandomizerKind::Bag7)
+    }
 
-            let mut game = game.lock().unwrap();
-            let new_pos = Position {
-                x: game.pos.x,
-                y: game.pos.y + 1,
-            };
-            if !is_collision(&game.field, &new_pos, &game.block) {
-                game.pos = new_pos;
-            } else {
-                if landing(&mut game).is_err() {
-                    gameover(&game);
-                    break;
+    pub fn new_game_with_seed(&self, seed: u64) -> Game {
+        let mut game = Game::new(seed, self.randomizer.build());
+        game.lock_delay = self.lock_delay;
+        if let Some(fumen) = self.fumen.as_deref().and_then(fumen::decode) {
+            game.load_fumen(&fumen);
+        }
+        game
+    }
+}
+
+pub fn normal(settings: &Settings) {
+    play(settings, Rule::Normal, None);
+}
+
+pub fn sprint(settings: &Settings, lines: usize) {
+    play(settings, Rule::Sprint(lines), None);
+}
+
+pub fn ultra(settings: &Settings, minutes: u64) {
+    play(settings, Rule::Ultra(minutes * 60_000), None);
+}
+
+pub fn marathon(settings: &Settings, lines: usize) {
+    play(settings, Rule::Marathon(lines), None);
+}
+
+pub fn dig(settings: &Settings, rows: usize) {
+    play(settings, Rule::Dig(rows), None);
+}
+
+pub fn survival(settings: &Settings) {
+    play(settings, Rule::Survival, None);
+}
+
+// play a single player game by the rule
+fn play(settings: &Settings, rule: Rule, mut bot: Option<AutoPlayer>) {
+    let mut game = settings.new_game();
+    rule.setup(&mut game);
+    let mut progress = Progress::new(rule);
+    // the bot does not break the records
+    progress.ranked = settings.ranked() && bot.is_none();
+    let Ok(mut broadcaster) = settings.broadcaster(game.seed) else {
+        return;
+    };
+
+    let mut replay = Replay::new(settings, game.seed, rule);
+
+    let input = Input::new();
+    if bot.is_none() {
+        input.warn_no_release();
+    }
+    let keymap = Keymap::default();
+    let mut controller = Controller::new(settings.handling);
+
+    println!("\x1b[2J\x1b[H\x1b[?25l");
+
+    let mut hint = Hint::default();
+    draw(&game);
+    progress.draw(&game);
+    if settings.pc_hint {
+        hint.draw(&game);
+    }
+
+    let start = time::Instant::now();
+    let mut drawn_at = 0;
+    let end = 'main: loop {
+        let mut changed = false;
+        for (action, pressed) in input.try_iter().filter_map(|event| keymap.map(&event)) {
+            if action == Action::Quit {
+                break 'main End::Quit;
+            }
+            // only the bot plays in the auto mode
+            if bot.is_some() {
+                continue;
+            }
+            replay.record(game.time, action, pressed);
+            if controller.input(&mut game, action, pressed).is_err() {
+                break 'main End::TopOut;
+            }
+            changed = true;
+        }
+
+        // advance the game to the current time
+        let now = start.elapsed().as_millis() as u64;
+        while game.time < now {
+            // the bot presses the keys in the game time
+            while let Some((action, pressed)) = bot.as_mut().and_then(|bot| bot.update(&game)) {
+                replay.record(game.time, action, pressed);
+                if controller.input(&mut game, action, pressed).is_err() {
+                    break 'main End::TopOut;
                 }
+                changed = true;
             }
+            match tick(&mut game, &mut controller, &mut progress) {
+                Ok(moved) => changed |= moved,
+                Err(end) => break 'main end,
+            }
+        }
 
+        // the panel of the rule has a timer
+        if changed || game.time >= drawn_at + FRAME_MSEC as u64 {
             draw(&game);
-        });
+            progress.draw(&game);
+            if settings.pc_hint {
+                hint.draw(&game);
+            }
+            drawn_at = game.time;
+            broadcast(
+                &mut broadcaster,
+                &Message::State(Box::new(Snapshot::new(&game, 0))),
+            );
+        }
+        thread::sleep(time::Duration::from_millis(1));
+    };
+
+    replay.end = game.time;
+    let saved = replay.save(&settings.replay_file);
+
+    match end {
+        End::Quit => (),
+        End::TopOut => {
+            broadcast(
+                &mut broadcaster,
+                &Message::State(Box::new(Snapshot::new(&game, 0))),
+            );
+            broadcast(&mut broadcaster, &Message::Lose);
+            progress.draw(&game);
+            progress.gameover(&game);
+            wait_quit(&input, &keymap);
+        }
+        End::Finish => {
+            broadcast(
+                &mut broadcaster,
+                &Message::State(Box::new(Snapshot::new(&game, 0))),
+            );
+            progress.finish(&game);
+            wait_quit(&input, &keymap);
+        }
+    }
+    drop(input);
+    quit();
+    if let Err(e) = saved {
+        eprintln!("failed to save the replay: {}", e);
     }
+}
 
-    let g = Getch::new();
-    loop {
-        match g.getch() {
-            Ok(Key::Left) => {
-                let mut game = game.lock().unwrap();
-                let new_pos = Position {
-                    x: game.pos.x.checked_sub(1).unwrap_or(game.pos.x),
-                    y: game.pos.y,
-                };
-                move_block(&mut game, new_pos);
-                draw(&game);
-            }
-            Ok(Key::Down) => {
-                let mut game = game.lock().unwrap();
-                let new_pos = Position {
-                    x: game.pos.x,
-                    y: game.pos.y + 1,
+// perfect clear by the blocks in sight, searched again when the block changes
+// the search runs in another thread not to stop the game
+#[derive(Default)]
+struct Hint {
+    solution: Option<Solution>,
+    // number of fixed blocks and the hold when searched
+    searched: Option<(usize, bool)>,
+    result: Option<mpsc::Receiver<Option<Solution>>>,
+}
+
+impl Hint {
+    fn draw(&mut self, game: &Game) {
+        let state = (game.stats.pieces, game.holded);
+        if self.searched != Some(state) {
+            let solver = Solver {
+                limit: Some(pc::HINT_LIMIT),
+                ..Solver::new(game, NEXT_LENGTH)
+            };
+            let field = game.field;
+            let (tx, rx) = mpsc::channel();
+            thread::spawn(move || tx.send(solver.solve(&field).into_iter().next()));
+            self.solution = None;
+            self.searched = Some(state);
+            self.result = Some(rx);
+        }
+        if let Some(solution) = self.result.as_ref().and_then(|rx| rx.try_recv().ok()) {
+            self.solution = solution;
+            self.result = None;
+        }
+        pc::draw_hint(game, self.solution.as_ref(), 0);
+    }
+}
+
+// play the replay back with the controls
+pub fn replay(path: &Path) {
+    let Some(replay) = Replay::load(path) else {
+        eprintln!("failed to load the replay {}", path.display());
+        return;
+    };
+    let mut playback = Playback::new(replay);
+
+    let input = Input::new();
+    println!("\x1b[2J\x1b[H\x1b[?25l");
+    draw(&playback.game);
+    playback.progress.draw(&playback.game);
+
+    let mut speed = 1.0;
+    let mut paused = false;
+    // game time to show, advanced by the speed
+    let mut target = 0.0;
+    let mut last = time::Instant::now();
+    let mut ended = false;
+    'main: loop {
+        let mut step = false;
+        for event in input.try_iter() {
+            match event {
+                KeyEvent::Press(Key::Char('q')) => break 'main,
+                KeyEvent::Press(Key::Char(' ')) => paused = !paused,
+                KeyEvent::Press(Key::Right) => step = true,
+                KeyEvent::Press(Key::Up) => speed = f64::min(speed * 2.0, REPLAY_SPEED_MAX),
+                KeyEvent::Press(Key::Down) => speed = f64::max(speed / 2.0, REPLAY_SPEED_MIN),
+                _ => (),
+            }
+        }
+
+        let now = time::Instant::now();
+        if !paused {
+            target += (now - last).as_secs_f64() * 1000.0 * speed;
+        }
+        last = now;
+        if step {
+            // pause at the next input
+            paused = true;
+            target = playback
+                .next_input()
+                .map_or(f64::MAX, |time| time as f64 + 1.0);
+        }
+
+        let mut changed = step;
+        while !ended && (playback.game.time as f64) < target {
+            match playback.step() {
+                Ok(moved) => changed |= moved,
+                Err(_) => ended = true,
+            }
+        }
+        target = target.min(playback.game.time as f64);
+
+        if changed || !paused {
+            draw(&playback.game);
+            playback.progress.draw(&playback.game);
+        }
+        print!(
+            "\x1b[24;1H\x1b[KREPLAY x{} {}",
+            speed,
+            if ended {
+                "END"
+            } else if paused {
+                "PAUSED"
+            } else {
+                ""
+            }
+        );
+        println!("\x1b[25;1Hspace: pause  right: step  up/down: speed  q: exit");
+        thread::sleep(time::Duration::from_millis(FRAME_MSEC as u64));
+    }
+    drop(input);
+    quit();
+}
+
+// two players in one terminal
+pub fn versus(settings: &Settings) {
+    versus_match(
+        settings,
+        &[Keymap::player1(), Keymap::player2()],
+        None,
+        ["PLAYER 1", "PLAYER 2"],
+    );
+}
+
+// a human against the bot
+pub fn versus_ai(settings: &Settings, bot: Bot) {
+    versus_match(settings, &[Keymap::default()], Some(bot), ["PLAYER", "BOT"]);
+}
+
+// players with the keymaps and the bot as the last player
+fn versus_match(settings: &Settings, keymaps: &[Keymap], mut bot: Option<Bot>, names: [&str; 2]) {
+    // the same blocks for both players
+    let seed = settings.seed.unwrap_or_else(rand::random);
+    let mut players =
+        [0, 1].map(|_| Player::new(settings.new_game_with_seed(seed), settings.handling));
+
+    let input = Input::new();
+    input.warn_no_release();
+
+    println!("\x1b[2J\x1b[H\x1b[?25l");
+    draw_players(&players);
+
+    let start = time::Instant::now();
+    let mut time = 0;
+    // the player topped out
+    let loser = 'main: loop {
+        let mut changed = false;
+        for event in input.try_iter() {
+            for (i, keymap) in keymaps.iter().enumerate() {
+                let Some((action, pressed)) = keymap.map(&event) else {
+                    continue;
                 };
-                move_block(&mut game, new_pos);
-                draw(&game);
-            }
-            Ok(Key::Right) => {
-                let mut game = game.lock().unwrap();
-                let new_pos = Position {
-                    x: game.pos.x + 1,
-                    y: game.pos.y,
+                if action == Action::Quit {
+                    break 'main None;
+                }
+                if players[i].input(action, pressed).is_err() {
+                    break 'main Some(i);
+                }
+                changed = true;
+            }
+        }
+
+        // advance both games to the current time
+        let now = start.elapsed().as_millis() as u64;
+        while time < now {
+            time += 1;
+            for (i, player) in players.iter_mut().enumerate() {
+                let result = match &mut bot {
+                    Some(bot) if i == 1 => bot.update(player),
+                    _ => player.update(),
                 };
-                move_block(&mut game, new_pos);
-                draw(&game);
-            }
-            Ok(Key::Char('z')) => {
-                let mut game = game.lock().unwrap();
-                rotate_left(&mut game);
-                draw(&game);
-            }
-            Ok(Key::Char('x')) => {
-                let mut game = game.lock().unwrap();
-                rotate_right(&mut game);
-                draw(&game);
-            }
-            Ok(Key::Up) => {
-                let mut game = game.lock().unwrap();
-                hard_drop(&mut game);
-                if landing(&mut game).is_err() {
-                    gameover(&game);
-                    break;
+                match result {
+                    Ok(moved) => changed |= moved,
+                    Err(()) => break 'main Some(i),
                 }
-                draw(&game);
             }
-            Ok(Key::Char(' ')) => {
-                let mut game = game.lock().unwrap();
-                hold(&mut game);
-                draw(&game);
+            send_garbage(&mut players);
+        }
+
+        if changed {
+            draw_players(&players);
+        }
+        thread::sleep(time::Duration::from_millis(1));
+    };
+
+    if let Some(loser) = loser {
+        draw_players(&players);
+        println!("\x1b[24;1H{} WINS", names[1 - loser]);
+        println!("press 'q' key to exit");
+        wait_quit(&input, &keymaps[0]);
+    }
+    drop(input);
+    quit();
+}
+
+// wait for the opponent over TCP and start the match
+pub fn host(settings: &Settings, port: u16) {
+    println!("waiting for the opponent on port {}", port);
+    let mut connection = match Connection::host(port) {
+        Ok(connection) => connection,
+        Err(e) => {
+            eprintln!("failed to host: {}", e);
+            return;
+        }
+    };
+    let seed = settings.seed.unwrap_or_else(rand::random);
+    if connection.send(&Message::Seed(seed)).is_ok() {
+        network_match(settings, connection, seed);
+    }
+}
+
+// join the match of the host
+pub fn join(settings: &Settings, addr: &str) {
+    let connection = match Connection::join(addr) {
+        Ok(connection) => connection,
+        Err(e) => {
+            eprintln!("failed to join {}: {}", addr, e);
+            return;
+        }
+    };
+    match connection.recv() {
+        Some(Message::Seed(seed)) => network_match(settings, connection, seed),
+        _ => eprintln!("the host closed the connection"),
+    }
+}
+
+// how the match over TCP ended
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Win,
+    Lose,
+    Disconnected,
+}
+
+impl Outcome {
+    fn text(self) -> &'static str {
+        match self {
+            Outcome::Win => "YOU WIN",
+            Outcome::Lose => "YOU LOSE",
+            Outcome::Disconnected => "DISCONNECTED",
+        }
+    }
+}
+
+// the local player on the left and the board of the opponent on the right
+fn network_match(settings: &Settings, mut connection: Connection, seed: u64) {
+    let mut player = Player::new(settings.new_game_with_seed(seed), settings.handling);
+    let mut opponent = settings.new_game_with_seed(seed);
+
+    let input = Input::new();
+    input.warn_no_release();
+    let keymap = Keymap::default();
+
+    println!("\x1b[2J\x1b[H\x1b[?25l");
+    draw_at(&player.game, PLAYER_LEFT[0]);
+    draw_at(&opponent, PLAYER_LEFT[1]);
+
+    let start = time::Instant::now();
+    // the board is sent at most once a frame
+    let mut sent_at = 0;
+    let mut dirty = true;
+    let result = 'main: loop {
+        let mut changed = false;
+        for (action, pressed) in input.try_iter().filter_map(|event| keymap.map(&event)) {
+            if action == Action::Quit {
+                break 'main None;
             }
-            Ok(Key::Char('q')) => {
-                break;
+            if player.input(action, pressed).is_err() {
+                break 'main Some(Outcome::Lose);
             }
-            _ => (),
+            changed = true;
         }
+
+        for message in connection.try_iter() {
+            match message {
+                Some(Message::State(snapshot)) => {
+                    let pending = snapshot.apply(&mut opponent);
+                    draw_at(&opponent, PLAYER_LEFT[1]);
+                    draw_meter(pending, PLAYER_LEFT[1]);
+                }
+                Some(Message::Garbage(lines)) => {
+                    player.receive(lines);
+                    changed = true;
+                }
+                Some(Message::Lose) => break 'main Some(Outcome::Win),
+                Some(Message::Seed(_)) => (),
+                None => break 'main Some(Outcome::Disconnected),
+            }
+        }
+
+        // advance the game to the current time
+        let now = start.elapsed().as_millis() as u64;
+        while player.game.time < now {
+            match player.update() {
+                Ok(moved) => changed |= moved,
+                Err(()) => break 'main Some(Outcome::Lose),
+            }
+        }
+
+        let outgoing = std::mem::take(&mut player.outgoing);
+        if outgoing > 0 && connection.send(&Message::Garbage(outgoing)).is_err() {
+            break 'main Some(Outcome::Disconnected);
+        }
+        if changed {
+            draw_at(&player.game, PLAYER_LEFT[0]);
+            draw_meter(player.pending(), PLAYER_LEFT[0]);
+        }
+        dirty |= changed;
+        if dirty && player.game.time >= sent_at + FRAME_MSEC as u64 {
+            let state = Message::State(Box::new(Snapshot::new(&player.game, player.pending())));
+            if connection.send(&state).is_err() {
+                break 'main Some(Outcome::Disconnected);
+            }
+            sent_at = player.game.time;
+            dirty = false;
+        }
+        thread::sleep(time::Duration::from_millis(1));
+    };
+
+    if let Some(result) = result {
+        if result == Outcome::Lose {
+            let state = Message::State(Box::new(Snapshot::new(&player.game, player.pending())));
+            connection.send(&state).ok();
+            connection.send(&Message::Lose).ok();
+        }
+        draw_at(&player.game, PLAYER_LEFT[0]);
+        draw_at(&opponent, PLAYER_LEFT[1]);
+        println!("\x1b[24;1H{}", result.text());
+        println!("press 'q' key to exit");
+        wait_quit(&input, &keymap);
     }
+    drop(input);
     quit();
 }
 
-pub fn auto() {
-    let _ = thread::spawn(|| {
-        let mut game = Game::new();
+// print the perfect clears of the field, each placement as a fumen
+pub fn perfect_clear(settings: &Settings, queue: Option<&str>, hold: Option<&str>, all: bool) {
+    let game = settings.new_game();
+    let mut solver = Solver::new(&game, QUEUE_LENGTH);
+    if let Some(queue) = queue.and_then(pc::parse_queue) {
+        solver.pieces = queue;
+    }
+    if let Some(hold) = hold.and_then(pc::parse_queue) {
+        solver.hold = hold.first().copied();
+    }
+    solver.first = !all;
+
+    let solutions = solver.solve(&game.field);
+    if solutions.is_empty() {
+        println!("no perfect clear");
+    }
+    for (i, solution) in solutions.iter().enumerate() {
+        println!("solution {}:", i + 1);
+        print!("{}", pc::format_solution(&game, solution));
+    }
+}
+
+// print the placement of the bot as a fumen
+pub fn analyze(settings: &Settings, weights: &GenoSeq, lookahead: Lookahead) {
+    let game = settings.new_game();
+    let elite = search(&game, weights, lookahead);
+    println!("field: {}", fumen::encode(&game.field, Some(&game.piece)));
+    println!(
+        "best:  {}",
+        fumen::encode(&game.field, Some(&elite.placement.piece))
+    );
+
+    let inputs = elite.inputs();
+    let names: Vec<_> = inputs.iter().map(|&action| action_name(action)).collect();
+    println!("keys:  {}", names.join(" "));
+}
 
-        println!("\x1b[2J\x1b[H\x1b[?25l");
+fn broadcast(broadcaster: &mut Option<Broadcaster>, message: &Message) {
+    if let Some(broadcaster) = broadcaster {
+        broadcaster.send(message);
+    }
+}
 
-        draw(&game);
+// draw the game broadcasted by another process
+pub fn watch(addr: &str) {
+    let connection = match Connection::join(addr) {
+        Ok(connection) => connection,
+        Err(e) => {
+            eprintln!("failed to watch {}: {}", addr, e);
+            return;
+        }
+    };
+    let Some(Message::Seed(seed)) = connection.recv() else {
+        eprintln!("the game is closed");
+        return;
+    };
+    let mut game = Game::new(seed, RandomizerKind::Bag7.build());
 
-        loop {
-            // sleep
-            // thread::sleep(time::Duration::from_millis(1));
-            
-            // get elite after the eval
-            let elite = eval(&game, &[100, 1, 10, 100]);
-            game = elite;
+    let input = Input::new();
+    let keymap = Keymap::default();
+    println!("\x1b[2J\x1b[H\x1b[?25l");
+    draw(&game);
 
-            if landing(&mut game).is_err() {
-                gameover(&game);
-                break;
+    let result = 'main: loop {
+        for (action, _) in input.try_iter().filter_map(|event| keymap.map(&event)) {
+            if action == Action::Quit {
+                break 'main None;
             }
-            draw(&game);
         }
-    });
+        for message in connection.try_iter() {
+            match message {
+                Some(Message::State(snapshot)) => {
+                    snapshot.apply(&mut game);
+                    draw(&game);
+                }
+                Some(Message::Lose) => break 'main Some("GAMEOVER"),
+                Some(_) => (),
+                None => break 'main Some("DISCONNECTED"),
+            }
+        }
+        thread::sleep(time::Duration::from_millis(1));
+    };
 
-    let g = Getch::new();
-    loop {
-        if let Ok(Key::Char('q')) = g.getch() {
+    if let Some(result) = result {
+        println!("{}", result);
+        println!("press 'q' key to exit");
+        wait_quit(&input, &keymap);
+    }
+    drop(input);
+    quit();
+}
+
+fn wait_quit(input: &Input, keymap: &Keymap) {
+    while let Some(event) = input.recv() {
+        if let Some((Action::Quit, true)) = keymap.map(&event) {
             break;
         }
     }
+}
 
-    quit();
+// the bot plays by the keys at the speed
+pub fn auto(settings: &Settings, lookahead: Lookahead, delay: u64) {
+    play(
+        settings,
+        Rule::Normal,
+        Some(AutoPlayer::new(WEIGHTS, lookahead, delay)),
+    );
 }
@@ -0,0 +1,178 @@
+use crate::blocks::{gen_block_7, Blockkind, BLOCK_KINDS};
+use clap::ValueEnum;
+use rand::{rngs::StdRng, seq::SliceRandom, Rng};
+use std::collections::VecDeque;
+
+// generator of the block sequence
+pub trait Randomizer: RandomizerClone + Send {
+    fn next_block(&mut self, rng: &mut StdRng) -> Blockkind;
+}
+
+pub trait RandomizerClone {
+    fn clone_box(&self) -> Box<dyn Randomizer>;
+}
+
+impl<T: Randomizer + Clone + 'static> RandomizerClone for T {
+    fn clone_box(&self) -> Box<dyn Randomizer> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn Randomizer> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum RandomizerKind {
+    /// 7 blocks shuffled in a bag
+    Bag7,
+    /// 2 of each block shuffled in a bag
+    Bag14,
+    /// Every block at random
+    Random,
+    /// Reroll once on a repeated block (NES)
+    Nes,
+    /// Up to 4 rolls to avoid the last 4 blocks (TGM)
+    Tgm,
+}
+
+impl RandomizerKind {
+    pub fn build(self) -> Box<dyn Randomizer> {
+        match self {
+            RandomizerKind::Bag7 => Box::new(Bag7::default()),
+            RandomizerKind::Bag14 => Box::new(Bag14::default()),
+            RandomizerKind::Random => Box::new(Random),
+            RandomizerKind::Nes => Box::new(Nes::default()),
+            RandomizerKind::Tgm => Box::new(Tgm::default()),
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Bag7 {
+    bag: VecDeque<Blockkind>,
+}
+
+impl Randomizer for Bag7 {
+    fn next_block(&mut self, rng: &mut StdRng) -> Blockkind {
+        if self.bag.is_empty() {
+            self.bag = gen_block_7(rng).into();
+        }
+        self.bag.pop_front().unwrap()
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Bag14 {
+    bag: Vec<Blockkind>,
+}
+
+impl Randomizer for Bag14 {
+    fn next_block(&mut self, rng: &mut StdRng) -> Blockkind {
+        if self.bag.is_empty() {
+            self.bag = [BLOCK_KINDS, BLOCK_KINDS].concat();
+            self.bag.shuffle(rng);
+        }
+        self.bag.pop().unwrap()
+    }
+}
+
+#[derive(Clone)]
+pub struct Random;
+
+impl Randomizer for Random {
+    fn next_block(&mut self, rng: &mut StdRng) -> Blockkind {
+        rng.gen()
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Nes {
+    last: Option<Blockkind>,
+}
+
+impl Randomizer for Nes {
+    fn next_block(&mut self, rng: &mut StdRng) -> Blockkind {
+        // the 8th roll is a reroll as well as the last block
+        let block = match BLOCK_KINDS.get(rng.gen_range(0..=BLOCK_KINDS.len())) {
+            Some(&block) if Some(block) != self.last => block,
+            _ => rng.gen(),
+        };
+        self.last = Some(block);
+        block
+    }
+}
+
+// number of rolls to avoid the blocks in the history
+const TGM_ROLLS: usize = 4;
+
+#[derive(Clone)]
+pub struct Tgm {
+    history: VecDeque<Blockkind>,
+    first: bool,
+}
+
+impl Default for Tgm {
+    fn default() -> Tgm {
+        Tgm {
+            history: [Blockkind::Z; 4].into(),
+            first: true,
+        }
+    }
+}
+
+impl Randomizer for Tgm {
+    fn next_block(&mut self, rng: &mut StdRng) -> Blockkind {
+        let block = if self.first {
+            // the first block is never S, Z or O
+            self.first = false;
+            *[Blockkind::I, Blockkind::J, Blockkind::L, Blockkind::T]
+                .choose(rng)
+                .unwrap()
+        } else {
+            let mut block = rng.gen();
+            for _ in 1..TGM_ROLLS {
+                if !self.history.contains(&block) {
+                    break;
+                }
+                block = rng.gen();
+            }
+            block
+        };
+        self.history.pop_front();
+        self.history.push_back(block);
+        block
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_bags() {
+        let mut rng = StdRng::seed_from_u64(0);
+        for (kind, size) in [(RandomizerKind::Bag7, 7), (RandomizerKind::Bag14, 14)] {
+            let mut randomizer = kind.build();
+            for _ in 0..3 {
+                let mut count = [0; 7];
+                for _ in 0..size {
+                    count[randomizer.next_block(&mut rng) as usize] += 1;
+                }
+                assert!(count.iter().all(|&c| c == size / 7));
+            }
+        }
+    }
+
+    #[test]
+    fn test_tgm_first() {
+        for seed in 0..100 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let block = RandomizerKind::Tgm.build().next_block(&mut rng);
+            assert!(![Blockkind::S, Blockkind::Z, Blockkind::O].contains(&block));
+        }
+    }
+}
@@ -0,0 +1,46 @@
+use std::path::PathBuf;
+use std::{env, fs};
+
+// personal records saved in the home directory
+const RECORD_FILE: &str = ".tetrust_records";
+
+fn path() -> PathBuf {
+    env::var_os("HOME")
+        .map(PathBuf::from)
+        .unwrap_or_default()
+        .join(RECORD_FILE)
+}
+
+// records as lines of "key value"
+fn load_all() -> Vec<(String, u64)> {
+    fs::read_to_string(path())
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .filter_map(|(key, value)| Some((key.to_string(), value.parse().ok()?)))
+        .collect()
+}
+
+pub fn load(key: &str) -> Option<u64> {
+    load_all()
+        .into_iter()
+        .find(|(k, _)| k == key)
+        .map(|(_, value)| value)
+}
+
+// save the value if it is better than the record
+// returns the previous record
+pub fn update(key: &str, value: u64, is_better: impl Fn(u64, u64) -> bool) -> Option<u64> {
+    let best = load(key);
+    if best.is_none_or(|best| is_better(value, best)) {
+        let mut records = load_all();
+        records.retain(|(k, _)| k != key);
+        records.push((key.to_string(), value));
+        let text: String = records
+            .iter()
+            .map(|(key, value)| format!("{key} {value}\n"))
+            .collect();
+        fs::write(path(), text).ok();
+    }
+    best
+}
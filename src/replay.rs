@@ -0,0 +1,278 @@
+use crate::fumen;
+use crate::game::Game;
+use crate::input::{Action, Controller, Handling};
+use crate::play::Settings;
+use crate::randomizer::RandomizerKind;
+use crate::rule::{tick, End, Progress, Rule};
+use clap::ValueEnum;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::{env, io};
+
+// the last game is saved in the home directory
+const REPLAY_FILE: &str = ".tetrust_replay";
+
+const ACTION_NAMES: [(Action, &str); 7] = [
+    (Action::Left, "left"),
+    (Action::Right, "right"),
+    (Action::SoftDrop, "soft_drop"),
+    (Action::HardDrop, "hard_drop"),
+    (Action::RotateLeft, "rotate_left"),
+    (Action::RotateRight, "rotate_right"),
+    (Action::Hold, "hold"),
+];
+
+pub fn default_path() -> PathBuf {
+    env::var_os("HOME")
+        .map(PathBuf::from)
+        .unwrap_or_default()
+        .join(REPLAY_FILE)
+}
+
+pub fn action_name(action: Action) -> &'static str {
+    ACTION_NAMES
+        .iter()
+        .find(|(a, _)| *a == action)
+        .map_or("quit", |(_, name)| name)
+}
+
+// everything to play the same game again
+pub struct Replay {
+    pub seed: u64,
+    pub randomizer: RandomizerKind,
+    pub lock_delay: u64,
+    pub handling: Handling,
+    pub rule: Rule,
+    pub fumen: Option<String>,
+    // game time, action and whether it is pressed
+    pub inputs: Vec<(u64, Action, bool)>,
+    // game time when the game ended
+    pub end: u64,
+}
+
+impl Replay {
+    pub fn new(settings: &Settings, seed: u64, rule: Rule) -> Replay {
+        Replay {
+            seed,
+            randomizer: settings.randomizer,
+            lock_delay: settings.lock_delay,
+            handling: settings.handling,
+            rule,
+            fumen: settings.fumen.clone(),
+            inputs: vec![],
+            end: 0,
+        }
+    }
+
+    pub fn record(&mut self, time: u64, action: Action, pressed: bool) {
+        self.inputs.push((time, action, pressed));
+    }
+
+    // the same game as recorded
+    pub fn new_game(&self) -> Game {
+        let mut game = Game::new(self.seed, self.randomizer.build());
+        game.lock_delay = self.lock_delay;
+        if let Some(fumen) = self.fumen.as_deref().and_then(fumen::decode) {
+            game.load_fumen(&fumen);
+        }
+        self.rule.setup(&mut game);
+        game
+    }
+
+    // lines of "name values" followed by the inputs
+    pub fn encode(&self) -> String {
+        let mut text = String::new();
+        let randomizer = self.randomizer.to_possible_value().unwrap();
+        writeln!(text, "seed {}", self.seed).ok();
+        writeln!(text, "randomizer {}", randomizer.get_name()).ok();
+        writeln!(text, "lock_delay {}", self.lock_delay).ok();
+        writeln!(
+            text,
+            "handling {} {} {}",
+            self.handling.das, self.handling.arr, self.handling.sdf
+        )
+        .ok();
+        writeln!(text, "rule {}", rule_name(self.rule)).ok();
+        writeln!(text, "fumen {}", self.fumen.as_deref().unwrap_or("-")).ok();
+        writeln!(text, "end {}", self.end).ok();
+        for (time, action, pressed) in &self.inputs {
+            let name = action_name(*action);
+            let event = if *pressed { "press" } else { "release" };
+            writeln!(text, "{} {} {}", time, name, event).ok();
+        }
+        text
+    }
+
+    pub fn decode(text: &str) -> Option<Replay> {
+        let mut lines = text.lines().map(|line| line.split(' ').collect::<Vec<_>>());
+        let mut value = |name: &str| {
+            let line = lines.next()?;
+            (line.first() == Some(&name)).then(|| line[1..].join(" "))
+        };
+        let seed = value("seed")?.parse().ok()?;
+        let randomizer = RandomizerKind::from_str(&value("randomizer")?, true).ok()?;
+        let lock_delay = value("lock_delay")?.parse().ok()?;
+        let handling = value("handling")?
+            .split(' ')
+            .map(|v| v.parse().ok())
+            .collect::<Option<Vec<u64>>>()?;
+        let rule = rule_of(&value("rule")?)?;
+        let fumen = Some(value("fumen")?).filter(|fumen| fumen != "-");
+        let end = value("end")?.parse().ok()?;
+        let inputs = lines
+            .map(|line| {
+                let [time, name, event] = line[..] else {
+                    return None;
+                };
+                let action = ACTION_NAMES.iter().find(|(_, n)| *n == name)?.0;
+                Some((time.parse().ok()?, action, event == "press"))
+            })
+            .collect::<Option<_>>()?;
+        Some(Replay {
+            seed,
+            randomizer,
+            lock_delay,
+            handling: Handling {
+                das: *handling.first()?,
+                arr: *handling.get(1)?,
+                sdf: *handling.get(2)?,
+            },
+            rule,
+            fumen,
+            inputs,
+            end,
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.encode())
+    }
+
+    pub fn load(path: &Path) -> Option<Replay> {
+        Replay::decode(&fs::read_to_string(path).ok()?)
+    }
+}
+
+// a game played by the replay
+pub struct Playback {
+    pub game: Game,
+    pub progress: Progress,
+    controller: Controller,
+    replay: Replay,
+    // index of the next input
+    next: usize,
+}
+
+impl Playback {
+    pub fn new(replay: Replay) -> Playback {
+        Playback {
+            game: replay.new_game(),
+            progress: Progress::new(replay.rule),
+            controller: Controller::new(replay.handling),
+            replay,
+            next: 0,
+        }
+    }
+
+    // game time of the next input
+    pub fn next_input(&self) -> Option<u64> {
+        self.replay.inputs.get(self.next).map(|input| input.0)
+    }
+
+    // apply the inputs at the time and advance the game by 1 millisecond
+    // in the same order as the game was played
+    pub fn step(&mut self) -> Result<bool, End> {
+        let mut changed = false;
+        while let Some(&(time, action, pressed)) = self.replay.inputs.get(self.next) {
+            if time != self.game.time {
+                break;
+            }
+            self.next += 1;
+            if self
+                .controller
+                .input(&mut self.game, action, pressed)
+                .is_err()
+            {
+                return Err(End::TopOut);
+            }
+            changed = true;
+        }
+        if self.game.time >= self.replay.end {
+            return Err(End::Quit);
+        }
+        let moved = tick(&mut self.game, &mut self.controller, &mut self.progress)?;
+        Ok(changed || moved)
+    }
+}
+
+fn rule_name(rule: Rule) -> String {
+    match rule {
+        Rule::Normal => "normal".to_string(),
+        Rule::Sprint(lines) => format!("sprint {}", lines),
+        Rule::Ultra(limit) => format!("ultra {}", limit),
+        Rule::Marathon(lines) => format!("marathon {}", lines),
+        Rule::Dig(rows) => format!("dig {}", rows),
+        Rule::Survival => "survival".to_string(),
+    }
+}
+
+fn rule_of(name: &str) -> Option<Rule> {
+    let (name, arg) = name.split_once(' ').unwrap_or((name, ""));
+    match name {
+        "normal" => Some(Rule::Normal),
+        "sprint" => Some(Rule::Sprint(arg.parse().ok()?)),
+        "ultra" => Some(Rule::Ultra(arg.parse().ok()?)),
+        "marathon" => Some(Rule::Marathon(arg.parse().ok()?)),
+        "dig" => Some(Rule::Dig(arg.parse().ok()?)),
+        "survival" => Some(Rule::Survival),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replay() {
+        let settings = Settings {
+            lock_delay: 500,
+            handling: Handling::default(),
+            seed: None,
+            randomizer: RandomizerKind::Tgm,
+            broadcast: None,
+            replay_file: default_path(),
+            fumen: Some("v115@9gilGeglRpGeRpglCeRpAeilzhNeAgH".to_string()),
+            pc_hint: false,
+        };
+        let mut replay = Replay::new(&settings, 7, Rule::Dig(4));
+        for i in 0..6 {
+            replay.record(i * 300, Action::Left, true);
+            replay.record(i * 300 + 200, Action::Left, false);
+            replay.record(i * 300 + 250, Action::HardDrop, true);
+        }
+        replay.end = 5000;
+
+        let text = replay.encode();
+        let loaded = Replay::decode(&text).unwrap();
+        assert_eq!(loaded.encode(), text);
+
+        // the same game every time
+        let results: Vec<_> = [replay, loaded]
+            .into_iter()
+            .map(|replay| {
+                let mut playback = Playback::new(replay);
+                while playback.step().is_ok() {}
+                (
+                    playback.game.field,
+                    playback.game.time,
+                    playback.game.stats.pieces,
+                )
+            })
+            .collect();
+        assert!(results[0] == results[1]);
+        assert_eq!(results[0].1, 5000);
+        assert_eq!(results[0].2, 6);
+    }
+}
@@ -0,0 +1,366 @@
+use crate::game::*;
+use crate::input::Controller;
+use crate::record;
+use rand::Rng;
+
+// names of the blocks in order of Blockkind
+pub const KIND_NAMES: [char; 7] = ['I', 'O', 'S', 'Z', 'J', 'L', 'T'];
+
+// lines between the split times
+const SPLIT_LINES: usize = 10;
+
+// milliseconds between the rising garbage of the survival
+const SURVIVAL_INTERVAL_MSEC: u64 = 8000;
+const SURVIVAL_INTERVAL_MIN_MSEC: u64 = 1000;
+// the interval gets shorter by every rise
+const SURVIVAL_SPEEDUP_MSEC: u64 = 250;
+
+// rules of the single player modes
+#[derive(Clone, Copy)]
+pub enum Rule {
+    // play until the top out
+    Normal,
+    // clear the lines as fast as possible
+    Sprint(usize),
+    // score as much as possible in the milliseconds
+    Ultra(u64),
+    // clear the lines until the goal
+    Marathon(usize),
+    // dig through the rows of garbage
+    Dig(usize),
+    // survive the rising garbage
+    Survival,
+}
+
+// most garbage rows of the dig, leaving room to spawn the blocks
+pub const MAX_DIG_ROWS: usize = 17;
+
+impl Rule {
+    // prepare the field of a new game
+    pub fn setup(&self, game: &mut Game) {
+        if let Rule::Dig(rows) = *self {
+            game.fill_garbage(rows);
+        }
+    }
+}
+
+// progress of the rule in a game
+pub struct Progress {
+    rule: Rule,
+    // time when every SPLIT_LINES lines are cleared
+    splits: Vec<u64>,
+    // time of the next rising garbage
+    garbage_at: u64,
+    rises: u64,
+    // the records are saved
+    pub ranked: bool,
+}
+
+impl Progress {
+    pub fn new(rule: Rule) -> Progress {
+        Progress {
+            rule,
+            splits: vec![],
+            garbage_at: SURVIVAL_INTERVAL_MSEC,
+            rises: 0,
+            ranked: true,
+        }
+    }
+
+    // save the value if the game is ranked, returns the previous record
+    fn record(&self, key: &str, value: u64, is_better: impl Fn(u64, u64) -> bool) -> Option<u64> {
+        if self.ranked {
+            record::update(key, value, is_better)
+        } else {
+            record::load(key)
+        }
+    }
+
+    // check the rule after every tick, returns whether the game is finished
+    // or Err if the game is topped out
+    pub fn update(&mut self, game: &mut Game) -> Result<bool, ()> {
+        let finished = match self.rule {
+            Rule::Normal => false,
+            Rule::Sprint(lines) => {
+                while self.splits.len() < game.line.min(lines) / SPLIT_LINES {
+                    self.splits.push(game.time);
+                }
+                game.line >= lines
+            }
+            Rule::Ultra(limit) => game.time >= limit,
+            Rule::Marathon(lines) => game.line >= lines,
+            Rule::Dig(_) => garbage_rows(&game.field) == 0,
+            Rule::Survival => {
+                if game.time >= self.garbage_at {
+                    let hole = game.garbage_rng.gen_range(0..PLAYFIELD_WIDTH);
+                    insert_garbage(game, 1, hole)?;
+                    self.rises += 1;
+                    self.garbage_at += SURVIVAL_INTERVAL_MSEC
+                        .saturating_sub(self.rises * SURVIVAL_SPEEDUP_MSEC)
+                        .max(SURVIVAL_INTERVAL_MIN_MSEC);
+                }
+                false
+            }
+        };
+        Ok(finished)
+    }
+
+    // side panel of the rule
+    pub fn draw(&self, game: &Game) {
+        match self.rule {
+            Rule::Normal => (),
+            Rule::Sprint(lines) => {
+                println!("\x1b[0m\x1b[2;54HTIME");
+                println!("\x1b[3;54H{}", format_time(game.time));
+                println!("\x1b[5;54HPPS");
+                println!("\x1b[6;54H{:.2}", pps(game));
+                println!("\x1b[8;54HLEFT");
+                println!("\x1b[9;54H{}  ", lines.saturating_sub(game.line));
+                println!("\x1b[11;54HSPLITS");
+                for (i, split) in self.splits.iter().enumerate() {
+                    println!(
+                        "\x1b[{};54H{:>3} {}",
+                        i + 12,
+                        (i + 1) * SPLIT_LINES,
+                        format_time(*split)
+                    );
+                }
+            }
+            Rule::Ultra(limit) => {
+                println!("\x1b[0m\x1b[2;54HTIME LEFT");
+                println!("\x1b[3;54H{}", format_time(limit.saturating_sub(game.time)));
+                println!("\x1b[5;54HPPS");
+                println!("\x1b[6;54H{:.2}", pps(game));
+            }
+            Rule::Marathon(lines) => {
+                println!("\x1b[0m\x1b[2;54HTIME");
+                println!("\x1b[3;54H{}", format_time(game.time));
+                println!("\x1b[5;54HLEFT");
+                println!("\x1b[6;54H{}  ", lines.saturating_sub(game.line));
+            }
+            Rule::Dig(_) => {
+                println!("\x1b[0m\x1b[2;54HTIME");
+                println!("\x1b[3;54H{}", format_time(game.time));
+                println!("\x1b[5;54HGARBAGE");
+                println!("\x1b[6;54H{}  ", garbage_rows(&game.field));
+                println!("\x1b[8;54HPIECES");
+                println!("\x1b[9;54H{}", game.stats.pieces);
+            }
+            Rule::Survival => {
+                println!("\x1b[0m\x1b[2;54HTIME");
+                println!("\x1b[3;54H{}", format_time(game.time));
+                println!("\x1b[5;54HNEXT RISE");
+                println!(
+                    "\x1b[6;54H{}",
+                    format_time(self.garbage_at.saturating_sub(game.time))
+                );
+            }
+        }
+    }
+
+    // show the top out of the game
+    pub fn gameover(&self, game: &Game) {
+        match self.rule {
+            Rule::Marathon(_) => {
+                println!("\x1b[0m\x1b[2J\x1b[H");
+                summary("GAMEOVER", game);
+                println!("\npress 'q' key to exit");
+            }
+            Rule::Survival => {
+                let best = self.record("survival", game.time, |new, best| new > best);
+                println!("\x1b[0m\x1b[2J\x1b[H");
+                println!("GAMEOVER\n");
+                println!("SURVIVED {}", format_time(game.time));
+                println!("LINES    {}", game.line);
+                println!("PIECES   {}", game.stats.pieces);
+                match best {
+                    Some(best) if best >= game.time => println!("BEST     {}", format_time(best)),
+                    _ if self.ranked => println!("NEW RECORD"),
+                    _ => (),
+                }
+                println!("\npress 'q' key to exit");
+            }
+            _ => gameover(game),
+        }
+    }
+
+    // show the result of the finished game and save the record
+    pub fn finish(&self, game: &Game) {
+        // results screen
+        println!("\x1b[0m\x1b[2J\x1b[H");
+        match self.rule {
+            // no goal
+            Rule::Normal | Rule::Survival => (),
+            Rule::Sprint(lines) => {
+                let best =
+                    self.record(&format!("sprint{lines}"), game.time, |new, best| new < best);
+                println!("FINISH\n");
+                println!("TIME   {}", format_time(game.time));
+                println!("PPS    {:.2}", pps(game));
+                match best {
+                    Some(best) if best <= game.time => println!("BEST   {}", format_time(best)),
+                    _ if self.ranked => println!("NEW RECORD"),
+                    _ => (),
+                }
+            }
+            Rule::Ultra(limit) => {
+                let best = self.record(
+                    &format!("ultra{}", limit / 1000),
+                    game.score as u64,
+                    |new, best| new > best,
+                );
+                println!("TIME UP\n");
+                println!("SCORE  {}", game.score);
+                println!("LINES  {}", game.line);
+                println!("LEVEL  {}", game.level);
+                println!("PPS    {:.2}", pps(game));
+                match best {
+                    Some(best) if best >= game.score as u64 => println!("BEST   {}", best),
+                    _ if self.ranked => println!("NEW RECORD"),
+                    _ => (),
+                }
+            }
+            Rule::Marathon(_) => summary("FINISH", game),
+            Rule::Dig(rows) => {
+                let best = self.record(&format!("dig{rows}"), game.time, |new, best| new < best);
+                println!("FINISH\n");
+                println!("TIME   {}", format_time(game.time));
+                println!("PIECES {}", game.stats.pieces);
+                match best {
+                    Some(best) if best <= game.time => println!("BEST   {}", format_time(best)),
+                    _ if self.ranked => println!("NEW RECORD"),
+                    _ => (),
+                }
+            }
+        }
+        println!("\npress 'q' key to exit");
+    }
+}
+
+// summary of the whole game
+fn summary(title: &str, game: &Game) {
+    println!("{title}\n");
+    println!("SCORE    {}", game.score);
+    println!("LINES    {}", game.line);
+    println!("LEVEL    {}", game.level);
+    println!("TIME     {}", format_time(game.time));
+    println!("TETRISES {}", game.stats.tetrises);
+    println!("T-SPINS  {}", game.stats.tspins);
+    println!("\nPIECES   {}", game.stats.pieces);
+    for (name, count) in KIND_NAMES.iter().zip(game.stats.kinds) {
+        println!("{name}        {count}");
+    }
+}
+
+// how the game ended
+pub enum End {
+    Quit,
+    TopOut,
+    Finish,
+}
+
+// advance the game by 1 millisecond under the rule, returns whether the block moved
+pub fn tick(
+    game: &mut Game,
+    controller: &mut Controller,
+    progress: &mut Progress,
+) -> Result<bool, End> {
+    let mut moved = controller.update(game);
+    moved |= update(game).map_err(|()| End::TopOut)?;
+    match progress.update(game) {
+        Ok(true) => Err(End::Finish),
+        Ok(false) => Ok(moved),
+        Err(()) => Err(End::TopOut),
+    }
+}
+
+// m:ss.mmm
+pub fn format_time(msec: u64) -> String {
+    format!(
+        "{}:{:02}.{:03}",
+        msec / 60_000,
+        msec / 1000 % 60,
+        msec % 1000
+    )
+}
+
+// pieces per second
+fn pps(game: &Game) -> f64 {
+    if game.time == 0 {
+        0.0
+    } else {
+        game.stats.pieces as f64 * 1000.0 / game.time as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blocks::{block_kind, Blockkind, Rotation, BLOCK_KINDS};
+
+    #[test]
+    fn test_dig_max_rows() {
+        for kind in BLOCK_KINDS {
+            let mut game = Game::new(0, Box::<crate::randomizer::Bag7>::default());
+            Rule::Dig(MAX_DIG_ROWS).setup(&mut game);
+            assert_eq!(garbage_rows(&game.field), MAX_DIG_ROWS);
+            game.next.push_front(kind);
+            assert!(spawn_block(&mut game).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_sprint_splits() {
+        let mut game = Game::new(0, Box::<crate::randomizer::Bag7>::default());
+        let mut progress = Progress::new(Rule::Sprint(40));
+        game.line = 12;
+        game.time = 15_000;
+        assert_eq!(progress.update(&mut game), Ok(false));
+        game.line = 41;
+        game.time = 61_234;
+        assert_eq!(progress.update(&mut game), Ok(true));
+        assert_eq!(progress.splits, [15_000, 61_234, 61_234, 61_234]);
+        assert_eq!(format_time(game.time), "1:01.234");
+    }
+
+    #[test]
+    fn test_ultra_time_up() {
+        let mut game = Game::new(0, Box::<crate::randomizer::Bag7>::default());
+        let mut progress = Progress::new(Rule::Ultra(2 * 60_000));
+        game.time = 2 * 60_000 - 1;
+        assert_eq!(progress.update(&mut game), Ok(false));
+        // the game ends at the time limit
+        game.time = 2 * 60_000;
+        assert_eq!(progress.update(&mut game), Ok(true));
+    }
+
+    #[test]
+    fn test_marathon_goal() {
+        let mut game = Game::new(0, Box::<crate::randomizer::Bag7>::default());
+        let mut progress = Progress::new(Rule::Marathon(150));
+        game.line = 149;
+        assert_eq!(progress.update(&mut game), Ok(false));
+        game.line = 150;
+        assert_eq!(progress.update(&mut game), Ok(true));
+
+        // the tetris and the block are counted for the summary
+        let mut game = Game::new(0, Box::<crate::randomizer::Bag7>::default());
+        for y in FIELD_HEIGHT - 6..FIELD_HEIGHT - 2 {
+            for x in 3..FIELD_WIDTH - 2 {
+                game.field[y][x] = block_kind::I;
+            }
+        }
+        game.piece = Piece {
+            rotation: Rotation::Right,
+            pos: Position {
+                x: 0,
+                y: FIELD_HEIGHT - 6,
+            },
+            ..Piece::new(Blockkind::I)
+        };
+        landing(&mut game).unwrap();
+        assert_eq!(game.line, 4);
+        assert_eq!(game.stats.tetrises, 1);
+        assert_eq!(game.stats.kinds, [1, 0, 0, 0, 0, 0, 0]);
+    }
+}
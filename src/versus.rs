@@ -0,0 +1,234 @@
+use crate::ai::{AutoPlayer, Lookahead, INPUT_DELAY_MSEC};
+use crate::ga::GenoSeq;
+use crate::game::*;
+use crate::input::{Action, Controller, Handling};
+use rand::Rng;
+use std::collections::VecDeque;
+
+// garbage lines sent by the line clears
+const ATTACK_TABLE: [usize; 5] = [0, 0, 1, 2, 4];
+const TSPIN_ATTACK_TABLE: [usize; 4] = [0, 2, 4, 6];
+const TSPIN_MINI_ATTACK_TABLE: [usize; 3] = [0, 0, 1];
+// garbage lines added by the combo
+const COMBO_ATTACK_TABLE: [usize; 12] = [0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 4, 5];
+const B2B_ATTACK: usize = 1;
+const PERFECT_CLEAR_ATTACK: usize = 10;
+// columns of the left end of each player
+pub const PLAYER_LEFT: [usize; 2] = [0, 58];
+// height of the pending garbage meter
+const METER_HEIGHT: usize = FIELD_HEIGHT - 2;
+
+// garbage lines sent by the clear
+pub fn attack(clear: &Clear) -> usize {
+    if clear.line == 0 {
+        return 0;
+    }
+    let mut lines = match clear.tspin {
+        TSpin::None => ATTACK_TABLE[clear.line],
+        TSpin::Mini => TSPIN_MINI_ATTACK_TABLE[clear.line],
+        TSpin::Full => TSPIN_ATTACK_TABLE[clear.line],
+    };
+    if clear.b2b {
+        lines += B2B_ATTACK;
+    }
+    let combo = clear.combo.unwrap_or(0);
+    lines += COMBO_ATTACK_TABLE[combo.min(COMBO_ATTACK_TABLE.len() - 1)];
+    if clear.perfect {
+        lines += PERFECT_CLEAR_ATTACK;
+    }
+    lines
+}
+
+// a game of the versus and the garbage between the opponent
+pub struct Player {
+    pub game: Game,
+    pub controller: Controller,
+    // garbage lines received and not inserted yet, in order of the attacks
+    pub garbage: VecDeque<usize>,
+    // garbage lines to send to the opponent
+    pub outgoing: usize,
+    // number of fixed blocks when the garbage was checked
+    pieces: usize,
+}
+
+impl Player {
+    pub fn new(game: Game, handling: Handling) -> Player {
+        Player {
+            game,
+            controller: Controller::new(handling),
+            garbage: VecDeque::new(),
+            outgoing: 0,
+            pieces: 0,
+        }
+    }
+
+    pub fn input(&mut self, action: Action, pressed: bool) -> Result<(), ()> {
+        self.controller.input(&mut self.game, action, pressed)?;
+        self.exchange_garbage()
+    }
+
+    // advance the game by 1 millisecond, returns whether the block moved
+    pub fn update(&mut self) -> Result<bool, ()> {
+        let mut moved = self.controller.update(&mut self.game);
+        moved |= update(&mut self.game)?;
+        self.exchange_garbage()?;
+        Ok(moved)
+    }
+
+    pub fn receive(&mut self, lines: usize) {
+        if lines > 0 {
+            self.garbage.push_back(lines);
+        }
+    }
+
+    pub fn pending(&self) -> usize {
+        self.garbage.iter().sum()
+    }
+
+    // after a block is fixed, the attack offsets the received garbage first
+    // and the garbage left rises when no lines are cleared
+    fn exchange_garbage(&mut self) -> Result<(), ()> {
+        if self.game.stats.pieces == self.pieces {
+            return Ok(());
+        }
+        self.pieces = self.game.stats.pieces;
+        let Some(clear) = self.game.last_clear else {
+            return Ok(());
+        };
+
+        if clear.line == 0 {
+            while let Some(lines) = self.garbage.pop_front() {
+                let hole = self.game.garbage_rng.gen_range(0..PLAYFIELD_WIDTH);
+                insert_garbage(&mut self.game, lines, hole)?;
+            }
+            return Ok(());
+        }
+
+        let mut lines = attack(&clear);
+        while lines > 0 {
+            let Some(front) = self.garbage.front_mut() else {
+                break;
+            };
+            let offset = lines.min(*front);
+            *front -= offset;
+            lines -= offset;
+            if *front == 0 {
+                self.garbage.pop_front();
+            }
+        }
+        self.outgoing += lines;
+        Ok(())
+    }
+}
+
+// the bot placing blocks at a fixed speed by the keys
+pub struct Bot {
+    auto: AutoPlayer,
+    // milliseconds for each block
+    interval: f64,
+    // game time of the next hard drop
+    drop_at: f64,
+}
+
+impl Bot {
+    pub fn new(pps: f64, weights: GenoSeq, lookahead: Lookahead) -> Bot {
+        let interval = 1000.0 / pps;
+        // fast enough to press about 10 keys for each block
+        let delay = ((interval / 10.0) as u64).clamp(1, INPUT_DELAY_MSEC);
+        Bot {
+            auto: AutoPlayer::new(weights, lookahead, delay),
+            interval,
+            drop_at: 0.0,
+        }
+    }
+
+    // advance the game of the bot by 1 millisecond
+    // the block is moved to the choice and hard dropped when its time comes
+    pub fn update(&mut self, player: &mut Player) -> Result<bool, ()> {
+        let pieces = player.game.stats.pieces;
+        let mut moved = false;
+        while let Some((action, pressed)) = self.auto.update(&player.game) {
+            player.input(action, pressed)?;
+            moved = true;
+        }
+        moved |= player.update()?;
+        if player.game.stats.pieces != pieces {
+            self.drop_at += self.interval;
+            self.auto.drop_at = self.drop_at as u64;
+        }
+        Ok(moved)
+    }
+}
+
+// send the garbage of each player to the other
+pub fn send_garbage(players: &mut [Player; 2]) {
+    let [p1, p2] = players;
+    p2.receive(std::mem::take(&mut p1.outgoing));
+    p1.receive(std::mem::take(&mut p2.outgoing));
+}
+
+// draw the games of both players
+pub fn draw_players(players: &[Player; 2]) {
+    for (player, left) in players.iter().zip(PLAYER_LEFT) {
+        draw_at(&player.game, left);
+        draw_meter(player.pending(), left);
+    }
+}
+
+// meter of the pending garbage between the field and the hold
+pub fn draw_meter(pending: usize, left: usize) {
+    for i in 0..METER_HEIGHT {
+        let color = if i < pending { "\x1b[41m" } else { "\x1b[0m" };
+        print!("\x1b[{};{}H{} ", METER_HEIGHT + 1 - i, left + 27, color);
+    }
+    println!("\x1b[0m");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::randomizer::Bag7;
+
+    #[test]
+    fn test_attack() {
+        let clear = Clear {
+            line: 2,
+            tspin: TSpin::Full,
+            combo: Some(2),
+            b2b: true,
+            perfect: false,
+        };
+        assert_eq!(attack(&clear), 4 + 1 + 1);
+
+        let handling = Handling::default();
+        let mut player = Player::new(Game::new(0, Box::<Bag7>::default()), handling);
+        player.receive(3);
+        player.receive(4);
+        player.game.last_clear = Some(clear);
+        player.game.stats.pieces += 1;
+        player.exchange_garbage().ok();
+        assert_eq!(player.pending(), 1);
+        assert_eq!(player.outgoing, 0);
+
+        // no lines cleared
+        player.game.last_clear = Some(Clear { line: 0, ..clear });
+        player.game.stats.pieces += 1;
+        player.exchange_garbage().ok();
+        assert_eq!(player.pending(), 0);
+        assert_eq!(garbage_rows(&player.game.field), 1);
+    }
+
+    #[test]
+    fn test_bot() {
+        let handling = Handling::default();
+        let mut player = Player::new(Game::new(0, Box::<Bag7>::default()), handling);
+        let mut bot = Bot::new(2.0, crate::ai::WEIGHTS, Lookahead::GREEDY);
+        // a block for each interval
+        for pieces in 1..=3 {
+            for _ in 0..bot.interval as usize {
+                bot.update(&mut player).unwrap();
+            }
+            assert_eq!(player.game.stats.pieces, pieces);
+        }
+    }
+}